@@ -0,0 +1,153 @@
+use bevy::prelude::*;
+use noise::{NoiseFn, Perlin};
+
+use crate::constants::{
+    SCULPT_RAISE_POWER,
+    SCULPT_LOWER_POWER,
+    SCULPT_RAMP_SLOPE,
+};
+use crate::height_map::HeightMap;
+use crate::surface::Surface;
+use crate::sheet::get_neighbours_radius;
+use crate::stamp::{BlendMode, Stamp, StampShape};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BrushKind {
+    Raise,
+    Lower,
+    Smooth,
+    Flatten,
+    Ramp,
+    Noise,
+    Paint(Surface), // changes the surface, not the height
+    Stamp(StampShape), // placed once at the start of a stroke
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SculptBrush {
+    pub kind: BrushKind,
+    pub radius: usize, // in cells
+    pub strength: f32,
+    pub scale: f32, // size of a stamp brush's stamp, 1 is as made
+    pub rotation: f32, // radians a stamp is turned from the stroke direction
+    pub blend: Option<BlendMode>, // how a stamp goes down, None is the shape's own
+}
+
+/// The brushes the player can pick from while sculpting.
+#[derive(Resource)]
+pub struct SculptBrushes {
+    pub brushes: Vec<SculptBrush>,
+    pub active: usize,
+}
+
+impl Default for SculptBrushes {
+    fn default() -> Self {
+        Self {
+            brushes: vec![
+                SculptBrush::new(BrushKind::Lower, 4, SCULPT_LOWER_POWER * 0.8),
+                SculptBrush::new(BrushKind::Raise, 4, SCULPT_RAISE_POWER * 0.8),
+                SculptBrush::new(BrushKind::Smooth, 5, 0.3),
+                SculptBrush::new(BrushKind::Flatten, 5, 0.2),
+                SculptBrush::new(BrushKind::Ramp, 6, 0.15),
+                SculptBrush::new(BrushKind::Noise, 4, 0.6),
+                SculptBrush::new(BrushKind::Paint(Surface::Ice), 3, 1.0),
+                SculptBrush::new(BrushKind::Stamp(StampShape::Kicker), 8, 6.0),
+            ],
+            active: 0,
+        }
+    }
+}
+
+impl SculptBrushes {
+    pub fn active(&self) -> SculptBrush {
+        self.brushes[self.active]
+    }
+}
+
+impl SculptBrush {
+    pub const fn new(kind: BrushKind, radius: usize, strength: f32) -> Self {
+        Self { kind, radius, strength, scale: 1.0, rotation: 0.0, blend: None }
+    }
+
+    /// The stamp a `Stamp` brush places, facing along `dir` turned by
+    /// `rotation`. Its radius is the size and strength the height, both
+    /// times `scale`.
+    pub fn stamp(&self, dir: Vec2, invert: bool) -> Option<Stamp> {
+        let BrushKind::Stamp(shape) = self.kind else { return None; };
+        let mut stamp = Stamp::new(shape, Vec2::splat(self.radius as f32), self.strength)
+            .scaled(self.scale)
+            .facing(dir);
+        stamp.rotation += self.rotation;
+        if let Some(blend) = self.blend {
+            stamp.blend = blend;
+        }
+        if invert {
+            stamp.height = -stamp.height;
+            stamp.blend = stamp.blend.invert();
+        }
+        Some(stamp)
+    }
+
+    /// Height changes for every cell under the brush centred at `cell`.
+    ///
+    /// * `dir` - direction of the stroke in sheet space (used by `Ramp`)
+    /// * `sample` - height sampled at the start of the stroke (used by `Flatten` and `Ramp`)
+    /// * `invert` - apply the opposite of the brush (raise <-> lower)
+    pub fn deltas(
+        &self,
+        hm: &HeightMap,
+        cell: (usize, usize),
+        dir: Vec2,
+        sample: f32,
+        invert: bool
+    ) -> Vec<(usize, usize, f32)> {
+        if let Some(stamp) = self.stamp(dir, invert) {
+            return stamp.deltas(hm, cell);
+        }
+        let sign = if invert { -1.0 } else { 1.0 };
+        let noise = Perlin::new(hm.seed);
+
+        get_neighbours_radius(cell.0, cell.1, self.radius, 1)
+            .into_iter()
+            .filter(|&(x, y, _)| x < hm.cell_w && y < hm.cell_h)
+            .map(|(x, y, n)| {
+                let falloff = 1.0 - (1.0 - n).powi(3);
+                let cur = hm.get(x, y);
+                let d = match self.kind {
+                    BrushKind::Raise => self.strength * sign,
+                    BrushKind::Lower => -self.strength * sign,
+                    BrushKind::Smooth => (average_around(hm, x, y) - cur) * self.strength,
+                    BrushKind::Flatten => (sample - cur) * self.strength,
+                    BrushKind::Ramp => {
+                        // Slope up from the sampled height in the stroke direction
+                        let off = Vec2::new(
+                            x as f32 - cell.0 as f32,
+                            y as f32 - cell.1 as f32
+                        );
+                        let target = sample + off.dot(dir) * SCULPT_RAMP_SLOPE * sign;
+                        (target.max(0.0) - cur) * self.strength
+                    },
+                    BrushKind::Noise => {
+                        let v = noise.get([x as f64 * 0.35, y as f64 * 0.35, 0.5]);
+                        v as f32 * self.strength * sign
+                    },
+                    BrushKind::Paint(_) | BrushKind::Stamp(_) => 0.0,
+                };
+                (x, y, d * falloff)
+            })
+            .collect()
+    }
+}
+
+/// Average height of the 3x3 cells around x/y
+fn average_around(hm: &HeightMap, x: usize, y: usize) -> f32 {
+    let mut total = 0.0;
+    let mut count = 0;
+    for j in y.saturating_sub(1)..=(y + 1).min(hm.cell_h - 1) {
+        for i in x.saturating_sub(1)..=(x + 1).min(hm.cell_w - 1) {
+            total += hm.get(i, j);
+            count += 1;
+        }
+    }
+    total / count as f32
+}
@@ -4,8 +4,8 @@ use bevy_atmosphere::settings::SkyboxCreationMode;
 use bevy_panorbit_camera::{PanOrbitCameraPlugin, PanOrbitCamera};
 
 use crate::constants::STONE_RADIUS;
-use crate::game::GameState;
-use crate::stone::Stone;
+use crate::game::{GamePhase, GameState};
+use crate::stone::ActiveStone;
 
 use std::f32::consts::*;
 
@@ -16,7 +16,9 @@ pub fn camera_plugin(app: &mut App) {
     ));
 
     app.add_systems(Startup, setup);
-    app.add_systems(OnEnter(GameState::InGame), (add_atmos, reset_cam));
+    app.add_systems(OnEnter(GameState::InGame), add_atmos);
+    // Back behind the start for every throw
+    app.add_systems(OnEnter(GamePhase::Aiming), reset_cam);
     app.add_systems(Update, cam_track_orbit);
     app.add_systems(OnExit(GameState::InGame), remove_atmos);
 }
@@ -45,7 +47,7 @@ fn setup(
 }
 
 pub fn cam_track_orbit(
-    stone: Query<&Transform, With<Stone>>,
+    stone: Query<&Transform, With<ActiveStone>>,
     mut camera: Query<&mut PanOrbitCamera>,
     keys: Res<ButtonInput<KeyCode>>,
 ){
@@ -1,18 +1,29 @@
+use std::ops::RangeInclusive;
+
 use avian3d::prelude::*;
 use bevy::{
     prelude::*,
     render::mesh::VertexAttributeValues
 };
 
+use crate::color_ramp::{ColorRamp, TerrainTheme};
 use crate::game::{OnGameScreen, CollisionLayer};
-use crate::height_map::HeightMap;
-use crate::sheet::Sheet;
+use crate::height_map::{DirtyRect, HeightMap};
+use crate::surface::SurfaceMap;
+use crate::water::WaterMap;
+use crate::coords::{SheetPos, WorldPos};
+use crate::sheet::{Sheet, SheetMode};
 use crate::constants::{
     CELL_SIZE,
     CHUNK_SIZE,
+    CHUNK_UNDERGROUND,
     NUM_CHUNKS,
 };
 
+// Vertices along each side of a chunk. The last row and column are
+// the same height map cells as the first ones of the next chunk.
+const CHUNK_VERTS: usize = CELL_SIZE + 1;
+
 pub struct SpawnChunk {
     pub pos: IVec2,
 }
@@ -22,17 +33,20 @@ impl Command for SpawnChunk {
         let xo = self.pos.x * CELL_SIZE as i32;
         let yo = self.pos.y * CELL_SIZE as i32;
 
-        let mut plane = Plane3d::default()
-            .mesh()
-            .size(CHUNK_SIZE, CHUNK_SIZE)
-            .subdivisions(CELL_SIZE as u32 - 2)
-            .build();
+        let mut plane = chunk_mesh();
 
         let hm = world
-            .get_resource_mut::<HeightMap>()
+            .get_resource::<HeightMap>()
             .expect("Height map should exist");
+        let surface = world
+            .get_resource::<SurfaceMap>()
+            .expect("Surface map should exist");
+        let ramp = world
+            .get_resource::<TerrainTheme>()
+            .map_or_else(ColorRamp::default, |theme| theme.ramp.clone());
 
-        sync_chunk_with_heightmap(&mut plane, &hm, xo, yo);
+        sync_chunk_with_heightmap(&mut plane, hm, surface, &ramp, xo, yo);
+        let collider = chunk_collider(hm, xo, yo);
 
         let mesh = world
             .get_resource_mut::<Assets<Mesh>>()
@@ -44,6 +58,8 @@ impl Command for SpawnChunk {
             .expect("StandardMaterial Assets to exist")
             .add(Color::WHITE);
 
+        let endless = world.get_resource::<SheetMode>() == Some(&SheetMode::Endless);
+
         // Mesh chunk
         let mut ent = world.spawn((
             Name::new("Chunk"),
@@ -51,25 +67,50 @@ impl Command for SpawnChunk {
             Mesh3d(mesh),
             RigidBody::Static,
             Friction::new(1.0),
-            ColliderConstructor::TrimeshFromMeshWithConfig(TrimeshFlags::FIX_INTERNAL_EDGES),
+            collider,
             //CollisionMargin(0.01),
             CollisionLayers::new(
                 [CollisionLayer::Terrain],
                 [CollisionLayer::Stone, CollisionLayer::Townsfolk]
             ),
             MeshMaterial3d(mat.clone()),
-            Transform::from_xyz(
-                self.pos.x as f32 * CHUNK_SIZE,
-                0.,
-                self.pos.y as f32 * CHUNK_SIZE,
-            ),
+            Transform::from_translation(chunk_translation(self.pos)),
             //Wireframe,
         ));
 
-        // Don't make the final target chunk a "Sheet".
-        if self.pos.y != NUM_CHUNKS - 1 {
+        // Don't make the final target chunk a "Sheet". Endless sheets don't have one.
+        if self.pos.y != NUM_CHUNKS - 1 || endless {
             ent.insert(Sheet);
         }
+        let chunk = ent.id();
+
+        // Water surface, showing wherever it's above the ground
+        if let Some(water) = world.get_resource::<WaterMap>() {
+            let water_mesh = chunk_water_mesh(water, xo, yo);
+            let mesh = world
+                .get_resource_mut::<Assets<Mesh>>()
+                .expect("Mesh Assets should exist")
+                .add(water_mesh);
+            let mat = world
+                .get_resource_mut::<Assets<StandardMaterial>>()
+                .expect("StandardMaterial Assets to exist")
+                .add(StandardMaterial {
+                    base_color: Color::srgba(0.1, 0.35, 0.6, 0.7),
+                    alpha_mode: AlphaMode::Blend,
+                    perceptual_roughness: 0.1,
+                    ..default()
+                });
+            world.spawn((
+                Name::new("Water"),
+                Mesh3d(mesh),
+                MeshMaterial3d(mat),
+                Transform::default(),
+            )).set_parent(chunk);
+        }
+
+        if !CHUNK_UNDERGROUND {
+            return;
+        }
 
         let mesh_underground = world
             .get_resource_mut::<Assets<Mesh>>()
@@ -86,8 +127,8 @@ impl Command for SpawnChunk {
         // terrain shaping (and the collider was re-built, seemed like a "just in that frame"
         // kind of bug... but when I changed to collisionlayers I forgot to
         // add the underground: yet all the time it never went through.
-        // So now is just because teh seems of chunks aren't joined. Might not
-        // need this anymore.
+        // Then it was because the seams of chunks weren't joined. They share
+        // their edge rows now, so it's off unless CHUNK_UNDERGROUND is set.
         world.spawn((
             OnGameScreen,
             RigidBody::Static,
@@ -103,57 +144,198 @@ impl Command for SpawnChunk {
                 [CollisionLayer::Terrain],
                 [CollisionLayer::Stone, CollisionLayer::Townsfolk]
             ),
-            Transform::from_xyz(
-                self.pos.x as f32 * CHUNK_SIZE,
-                -25.05,
-                self.pos.y as f32 * CHUNK_SIZE,
+            Transform::from_translation(
+                chunk_translation(self.pos) - Vec3::Y * 25.05
             ),
         ));
 
     }
 }
 
-pub fn sync_chunk_with_heightmap(mesh: &mut Mesh, map: &HeightMap, xo: i32, yo: i32) {
-    let vert = mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION).unwrap();
-    let VertexAttributeValues::Float32x3(vert_pos) = vert else {
-        panic!("Unexpected vertex format, expected Float32x3.");
+/// Flat chunk with a vertex on every cell corner
+pub fn chunk_mesh() -> Mesh {
+    Plane3d::default()
+        .mesh()
+        .size(CHUNK_SIZE, CHUNK_SIZE)
+        .subdivisions(CHUNK_VERTS as u32 - 2)
+        .build()
+}
+
+/// Copy the whole chunk at cell offset xo/yo from the heightmap
+pub fn sync_chunk_with_heightmap(
+    mesh: &mut Mesh,
+    map: &HeightMap,
+    surface: &SurfaceMap,
+    ramp: &ColorRamp,
+    xo: i32,
+    yo: i32
+) {
+    if mesh.attribute(Mesh::ATTRIBUTE_COLOR).is_none() {
+        let count = mesh.count_vertices();
+        mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, vec![[1.0; 4]; count]);
+    }
+    let all = DirtyRect {
+        min_x: xo as usize,
+        min_y: yo as usize,
+        max_x: xo as usize + CELL_SIZE,
+        max_y: yo as usize + CELL_SIZE,
     };
+    sync_chunk_rect(mesh, map, surface, ramp, xo, yo, all);
+}
 
-    // Copy the heightmap values to the plane
-    for y in 0..CELL_SIZE {
-        for x in 0..CELL_SIZE {
-            vert_pos[y * CELL_SIZE + x][1] = map.map[y + yo as usize][x + xo as usize] as f32;
+/// Update the heights of the chunk's vertices inside `rect` (in SHEET
+/// cells), and the normals and colors of those and their neighbours.
+pub fn sync_chunk_rect(
+    mesh: &mut Mesh,
+    map: &HeightMap,
+    surface: &SurfaceMap,
+    ramp: &ColorRamp,
+    xo: i32,
+    yo: i32,
+    rect: DirtyRect
+) {
+    let (xo, yo) = (xo as usize, yo as usize);
+
+    if let Some((xs, ys)) = chunk_cells(rect, xo, yo) {
+        let Some(VertexAttributeValues::Float32x3(vert_pos)) =
+            mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION) else {
+            panic!("Unexpected vertex format, expected Float32x3.");
+        };
+        for y in ys {
+            for x in xs.clone() {
+                vert_pos[(y - yo) * CHUNK_VERTS + x - xo][1] = vert_height(map, x, y);
+            }
         }
     }
 
-    // Set the colors
-    let cols = vert_height_to_color(vert_pos);
-    mesh.insert_attribute(
-        Mesh::ATTRIBUTE_COLOR,
-        cols,
-    );
+    // A height change tilts and bends the ground around it too
+    if let Some((xs, ys)) = chunk_cells(rect.grow(1), xo, yo) {
+        let Some(VertexAttributeValues::Float32x3(vert_norm)) =
+            mesh.attribute_mut(Mesh::ATTRIBUTE_NORMAL) else {
+            panic!("Unexpected normal format, expected Float32x3.");
+        };
+        for y in ys.clone() {
+            for x in xs.clone() {
+                let (cx, cy) = vert_cell(map, x, y);
+                vert_norm[(y - yo) * CHUNK_VERTS + x - xo] = map.cell_normal(cx, cy).to_array();
+            }
+        }
 
-    mesh.compute_normals();
+        let Some(VertexAttributeValues::Float32x4(vert_col)) =
+            mesh.attribute_mut(Mesh::ATTRIBUTE_COLOR) else {
+            panic!("Unexpected color format, expected Float32x4.");
+        };
+        for y in ys {
+            for x in xs.clone() {
+                let (cx, cy) = vert_cell(map, x, y);
+                vert_col[(y - yo) * CHUNK_VERTS + x - xo] = vert_color(map, surface, ramp, cx, cy);
+            }
+        }
+    }
 }
 
-pub fn vert_height_to_color(cols: &Vec<[f32; 3]>) -> Vec<[f32; 4]> {
-    cols
-        .iter()
-        .map(|[_, h, _]| {
-            let h = *h; // terrain_height;
-            let col;
-            if h > 40.0 {
-                col = Color::srgb(1.0, 0.9, 1.0);
-            } else if h > 18.0 {
-                col = Color::srgb(0.6, 0.5, 0.0);
-            } else if h > 7.0 {
-                col = Color::srgb(0.4, 0.9, 0.1);
-            } else if h > 1.0{
-                col = Color::srgb(0.4, 0.8, 0.1);
-            } else {
-                col = Color::srgb(0.26,0.7, 0.119);
-            }
-            col.to_linear().to_f32_array()
+/// The part of `rect` that is in the chunk at cell offset xo/yo
+fn chunk_cells(
+    rect: DirtyRect,
+    xo: usize,
+    yo: usize
+) -> Option<(RangeInclusive<usize>, RangeInclusive<usize>)> {
+    let xs = rect.min_x.max(xo)..=rect.max_x.min(xo + CELL_SIZE);
+    let ys = rect.min_y.max(yo)..=rect.max_y.min(yo + CELL_SIZE);
+    if xs.is_empty() || ys.is_empty() {
+        return None;
+    }
+    Some((xs, ys))
+}
+
+/// Indexes of the chunks that have vertices on cell rows in `rect`.
+/// A row on a chunk edge is in both chunks.
+pub fn chunks_with_rows(rect: DirtyRect) -> RangeInclusive<usize> {
+    rect.min_y.saturating_sub(1) / CELL_SIZE..=rect.max_y / CELL_SIZE
+}
+
+/// Indexes of the chunk columns that have vertices on cell columns in `rect`
+pub fn chunks_with_cols(rect: DirtyRect) -> RangeInclusive<usize> {
+    rect.min_x.saturating_sub(1) / CELL_SIZE..=rect.max_x / CELL_SIZE
+}
+
+/// WORLD position of the centre of chunk `pos`
+pub fn chunk_translation(pos: IVec2) -> Vec3 {
+    SheetPos::new(
+        (pos.x as f32 + 0.5) * CHUNK_SIZE,
+        0.0,
+        (pos.y as f32 + 0.5) * CHUNK_SIZE
+    ).to_world().0
+}
+
+/// Chunk at a WORLD position
+pub fn chunk_at(p: Vec3) -> IVec2 {
+    let p = WorldPos(p).to_sheet().0 / CHUNK_SIZE;
+    IVec2::new(p.x.floor() as i32, p.z.floor() as i32)
+}
+
+/// Height map cell for a vertex. Vertices past the far edges
+/// of the sheet reuse the last row/column of cells.
+fn vert_cell(map: &HeightMap, x: usize, y: usize) -> (usize, usize) {
+    (x.min(map.cell_w - 1), y.min(map.cell_h - 1))
+}
+
+fn vert_height(map: &HeightMap, x: usize, y: usize) -> f32 {
+    let (cx, cy) = vert_cell(map, x, y);
+    map.get(cx, cy)
+}
+
+/// Heightfield collider matching the chunk mesh at cell offset xo/yo
+pub fn chunk_collider(map: &HeightMap, xo: i32, yo: i32) -> Collider {
+    // Heightfields want the heights as [x][z]
+    let heights = (0..CHUNK_VERTS)
+        .map(|x| {
+            (0..CHUNK_VERTS)
+                .map(|y| vert_height(map, x + xo as usize, y + yo as usize))
+                .collect()
         })
-        .collect()
+        .collect();
+    Collider::heightfield(heights, Vec3::new(CHUNK_SIZE, 1.0, CHUNK_SIZE))
+}
+
+/// Flat sea unless there's a lake or river in the chunk
+pub fn chunk_water_mesh(water: &WaterMap, xo: i32, yo: i32) -> Mesh {
+    let (xo, yo) = (xo as usize, yo as usize);
+    let level = |x: usize, y: usize| water.level(x.min(water.cell_w - 1), y.min(water.cell_h - 1));
+    let flat = (yo..=yo + CELL_SIZE)
+        .all(|y| (xo..=xo + CELL_SIZE).all(|x| level(x, y) == water.sea_level));
+    if flat {
+        return Plane3d::default()
+            .mesh()
+            .size(CHUNK_SIZE, CHUNK_SIZE)
+            .build()
+            .translated_by(Vec3::Y * water.sea_level);
+    }
+
+    let mut mesh = chunk_mesh();
+    let Some(VertexAttributeValues::Float32x3(vert_pos)) =
+        mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION) else {
+        panic!("Unexpected vertex format, expected Float32x3.");
+    };
+    for y in 0..CHUNK_VERTS {
+        for x in 0..CHUNK_VERTS {
+            vert_pos[y * CHUNK_VERTS + x][1] = level(x + xo, y + yo);
+        }
+    }
+    mesh.compute_normals();
+    mesh
+}
+
+/// Vertex color for SHEET cell x/y, shaded by how steep and curved it is
+pub fn vert_color(
+    map: &HeightMap,
+    surface: &SurfaceMap,
+    ramp: &ColorRamp,
+    x: usize,
+    y: usize
+) -> [f32; 4] {
+    let normal = map.cell_normal(x, y);
+    let slope = normal.xz().length() / normal.y;
+    let base = surface.get(x, y).color(ramp, map.get(x, y));
+    ramp.shade(base, slope, map.cell_curvature(x, y))
 }
@@ -0,0 +1,173 @@
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+};
+use serde::Deserialize;
+
+use crate::game::GameState;
+use crate::surface::SurfaceMap;
+
+/// Terrain themes in assets/ramps, cycled with T
+pub const THEMES: [&str; 4] = ["summer", "winter", "desert", "night"];
+
+/// How the terrain is coloured: grass colour by height, tinted where
+/// the ground is steep, on ridges and in gullies. Loaded from a
+/// `.ramp` (RON) file, see assets/ramps.
+#[derive(Asset, TypePath, Deserialize, Debug, Clone)]
+pub struct ColorRamp {
+    pub name: String,
+    /// Heights and the sRGB colour there, lowest first. Blends between them.
+    pub stops: Vec<(f32, [f32; 3])>,
+    /// Scales every surface's colour, not just the grass
+    pub brightness: f32,
+    pub slope: Tint, // by rise over run
+    pub ridge: Tint, // by how much the ground bulges up
+    pub gully: Tint, // by how much the ground dips
+}
+
+/// Blend toward `color` as a value goes from `start` to `full`
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct Tint {
+    pub color: [f32; 3],
+    pub start: f32,
+    pub full: f32,
+    pub strength: f32,
+}
+
+impl Default for ColorRamp {
+    /// Summer, built in so there's something to draw before the themes load
+    fn default() -> Self {
+        ColorRamp::parse(include_str!("../assets/ramps/summer.ramp"))
+            .expect("Built in ramp should parse")
+    }
+}
+
+impl ColorRamp {
+    pub fn parse(text: &str) -> Result<Self, ron::error::SpannedError> {
+        ron::from_str(text)
+    }
+
+    /// Colour of grass at height `h`
+    pub fn height_color(&self, h: f32) -> LinearRgba {
+        let i = self.stops.partition_point(|(stop, _)| *stop <= h);
+        let col = match (self.stops.get(i.wrapping_sub(1)), self.stops.get(i)) {
+            (Some((h0, c0)), Some((h1, c1))) => {
+                let t = (h - h0) / (h1 - h0);
+                srgb(*c0).mix(&srgb(*c1), t)
+            },
+            (Some((_, c)), None) | (None, Some((_, c))) => srgb(*c),
+            (None, None) => Color::WHITE,
+        };
+        col.to_linear()
+    }
+
+    /// Vertex colour for ground of `base` colour that is `slope` steep
+    /// (rise over run) and curved by `curvature` (positive in hollows)
+    pub fn shade(&self, base: LinearRgba, slope: f32, curvature: f32) -> [f32; 4] {
+        let col = self.slope.apply(base, slope);
+        let col = self.ridge.apply(col, -curvature);
+        let col = self.gully.apply(col, curvature);
+        (col * self.brightness).with_alpha(1.0).to_f32_array()
+    }
+}
+
+impl Tint {
+    fn apply(&self, col: LinearRgba, value: f32) -> LinearRgba {
+        let t = ((value - self.start) / (self.full - self.start).max(f32::EPSILON)).clamp(0.0, 1.0);
+        col.mix(&srgb(self.color).to_linear(), t * self.strength)
+    }
+}
+
+fn srgb([r, g, b]: [f32; 3]) -> Color {
+    Color::srgb(r, g, b)
+}
+
+/// Loads the theme files
+#[derive(Default)]
+struct ColorRampLoader;
+
+impl AssetLoader for ColorRampLoader {
+    type Asset = ColorRamp;
+    type Settings = ();
+    type Error = std::io::Error;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<ColorRamp, Self::Error> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes).await?;
+        let text = std::str::from_utf8(&bytes)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        ColorRamp::parse(text)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["ramp"]
+    }
+}
+
+/// The ramp the chunks are coloured with, and the themes to pick from
+#[derive(Resource, Default)]
+pub struct TerrainTheme {
+    pub ramp: ColorRamp,
+    themes: Vec<Handle<ColorRamp>>,
+    current: usize,
+    shown: Option<AssetId<ColorRamp>>, // the theme `ramp` was copied from
+}
+
+pub fn color_ramp_plugin(app: &mut App) {
+    app
+        .init_asset::<ColorRamp>()
+        .init_asset_loader::<ColorRampLoader>()
+        .init_resource::<TerrainTheme>()
+        .add_systems(Startup, load_themes)
+        .add_systems(Update, (
+            cycle_theme,
+            apply_theme,
+        ).chain().run_if(in_state(GameState::InGame)));
+}
+
+/// Start with eg. `THEME=winter cargo run`
+fn load_themes(mut theme: ResMut<TerrainTheme>, asset_server: Res<AssetServer>) {
+    theme.themes = THEMES
+        .iter()
+        .map(|name| asset_server.load(format!("ramps/{}.ramp", name)))
+        .collect();
+    if let Ok(name) = std::env::var("THEME") {
+        match THEMES.iter().position(|t| *t == name) {
+            Some(i) => theme.current = i,
+            None => warn!("no theme called {}, try one of {:?}", name, THEMES),
+        }
+    }
+}
+
+fn cycle_theme(mut theme: ResMut<TerrainTheme>, keys: Res<ButtonInput<KeyCode>>) {
+    if keys.just_pressed(KeyCode::KeyT) {
+        theme.current = (theme.current + 1) % theme.themes.len().max(1);
+    }
+}
+
+/// Swap in the current theme once it has loaded (or is edited), and
+/// recolour every chunk with it
+fn apply_theme(
+    mut theme: ResMut<TerrainTheme>,
+    ramps: Res<Assets<ColorRamp>>,
+    mut events: EventReader<AssetEvent<ColorRamp>>,
+    surface_map: Option<ResMut<SurfaceMap>>,
+) {
+    let Some(id) = theme.themes.get(theme.current).map(|h| h.id()) else { return; };
+    let modified = events.read().any(|e| e.is_modified(id));
+    if theme.shown == Some(id) && !modified {
+        return;
+    }
+    let (Some(ramp), Some(mut surface_map)) = (ramps.get(id), surface_map) else { return; };
+
+    info!("terrain theme: {}", ramp.name);
+    theme.ramp = ramp.clone();
+    theme.shown = Some(id);
+    surface_map.mark_all_dirty();
+}
@@ -10,12 +10,21 @@ pub const STONE_ANGULAR_DAMPENING_INC_MAX_Y: f32 = 20.0;
 pub const STONE_ANGULAR_DAMPENING_INC_AMOUNT: f32 = 0.015; // * dt
 pub const STONE_MAX_VEL: f32 = 500.0;
 pub const STONE_STOP_VEL: f32 = 0.5;
+pub const STONES_PER_END: usize = 4; // throws before the end is scored
 
 pub const CHUNK_SIZE: f32 = 400.0;
 pub const NUM_CHUNKS: i32 = 15;
+pub const NUM_CHUNK_COLS: i32 = 1; // chunks across the sheet, eg. 3 for a wide one
 pub const CELL_SIZE: usize = 140;
+pub const CHUNK_UNDERGROUND: bool = false; // black boxes under the chunks to catch anything falling through
+
+pub const ENDLESS_CHUNKS_AHEAD: i32 = 4; // chunks kept spawned in front of the stone
+pub const ENDLESS_CHUNKS_BEHIND: i32 = 2; // and behind it, before they're dropped
 
 pub const SHEET_TOTAL: f32 = CHUNK_SIZE * NUM_CHUNKS as f32;
+pub const SHEET_WIDTH: f32 = CHUNK_SIZE * NUM_CHUNK_COLS as f32;
+// World position of SHEET 0,0: the right side of the sheet, at the start of the first chunk
+pub const SHEET_ORIGIN: Vec3 = Vec3::new(-SHEET_WIDTH * 0.5, 0.0, -CHUNK_SIZE * 0.5);
 pub const SHEET_PRE_AREA: f32 = 50.0;
 
 pub const STONE_X: f32 = 0.0;
@@ -25,12 +34,47 @@ pub const STONE_Z: f32 = -CHUNK_SIZE + SHEET_PRE_AREA;
 pub const TARGET_CENTRE: Vec3 = Vec3::new(0.0, 0.0, SHEET_TOTAL - CHUNK_SIZE);
 
 pub const MAX_TERRAIN_HEIGHT: f32 = 20.0;
+pub const MAX_BUILDING_SLOPE: f32 = 0.3; // rise over run
 
 pub const MIN_SCULT_DIST_FROM_STONE: f32 = 18.0;
 
+pub const IMPACT_MIN_SPEED: f32 = 40.0; // landing speed into the ground that leaves a crater
+pub const IMPACT_CRATER_SIZE: f32 = 0.06; // crater radius in cells per m/s of landing speed
+pub const IMPACT_CRATER_DEPTH: f32 = 0.02; // crater depth in metres per m/s of landing speed
+pub const IMPACT_MAX_CRATER_SIZE: f32 = 10.0; // in cells
+pub const GROOVE_MIN_SPEED: f32 = 5.0; // rolling slower than this doesn't carve
+pub const GROOVE_DEPTH: f32 = 0.15; // in metres, on the softest ground
+
+pub const ZONE_HOLE_RADIUS: f32 = 60.0; // no raising around the hole
+pub const ZONE_LAUNCH_LENGTH: f32 = SHEET_PRE_AREA * 2.0; // no editing the start of the sheet
+pub const ZONE_BUILDING_MARGIN: f32 = 4.0; // no digging out from under buildings
+
+pub const WATER_DRAG: f32 = 1.5; // fraction of the stone's speed lost per second, fully under
+pub const WATER_BUOYANCY: f32 = 1.0; // upward m/s per second, fully under
+pub const WATER_SINK_SPEED: f32 = 2.0; // slower than this in deep enough water and the stone has sunk
+pub const WATER_SINK_DEPTH: f32 = 5.0; // metres of water over the bottom of the stone to sink in
+
+pub const SWEEP_STAMINA: f32 = 4.0; // seconds of sweeping per throw
+pub const SWEEP_RECOVER: f32 = 0.25; // stamina seconds back per second not sweeping
+pub const SWEEP_AHEAD: f32 = STONE_RADIUS * 1.5; // metres in front of the stone swept
+pub const SWEEP_RADIUS: f32 = STONE_RADIUS; // size of a swept patch
+pub const SWEEP_PATCH_TIME: f32 = 3.0; // seconds a swept patch stays slick
+pub const SWEEP_FRICTION: f32 = 0.5; // times the surface's friction on swept ground
+pub const SWEEP_DAMPING: f32 = 0.25; // times the stone's linear damping on swept ground
+
 pub const STONE_HURL_POWERUP_TIME: f32 = 3.0; // seconds
 pub const STONE_HURL_TIME_TO_POWER_MULTIPLIER: f32 = 150.0;
 pub const STONE_HURL_AIM_ANGLE_MULTIPLIER: f32 = 200.0;
+pub const STONE_SPIN_STEP: f32 = 0.25; // spin added per Q/E press when aiming
+pub const STONE_SPIN_RATE: f32 = 2.0; // rad/s about the up axis at full spin
+pub const STONE_CURL: f32 = 4.0; // sideways m/s per second at full spin, when nearly stopped
+pub const STONE_CURL_SPEED: f32 = 30.0; // curl is half as strong at this speed
 
 pub const SCULPT_RAISE_POWER: f32 = 0.5;
 pub const SCULPT_LOWER_POWER: f32 = 0.5;
+pub const SCULPT_COLLIDER_DELAY: f32 = 0.15; // seconds after the terrain changes to rebuild the collider
+pub const SCULPT_RAMP_SLOPE: f32 = 0.3; // height per cell
+pub const SCULPT_SOIL_START: f32 = 1000.0; // in cubic metres, when SOIL is on
+pub const SCULPT_STAMP_SCALE_STEP: f32 = 1.25; // stamp brush grows (or shrinks) by this per [ or ] press
+pub const SCULPT_STAMP_SCALE_RANGE: (f32, f32) = (0.25, 4.0);
+pub const SCULPT_STAMP_ROTATE_STEP: f32 = 15.0; // degrees the stamp brush turns per , or . press
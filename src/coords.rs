@@ -0,0 +1,90 @@
+use bevy::prelude::*;
+
+use crate::constants::SHEET_ORIGIN;
+use crate::height_map::HeightMap;
+
+// Three spaces positions are in:
+// - WORLD: where things are drawn and collide. x = 0 is the middle of the
+//   sheet (-x is right looking down it), +z goes down the sheet.
+// - SHEET: metres from the right side (x) and start (z) of the sheet.
+//   Height (y) is the same as WORLD.
+// - CELL: a height map cell, x across and y down the sheet.
+
+/// A position in WORLD space
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct WorldPos(pub Vec3);
+
+/// A position in SHEET space
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SheetPos(pub Vec3);
+
+/// A height map cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct CellIdx {
+    pub x: usize,
+    pub y: usize,
+}
+
+impl WorldPos {
+    pub fn to_sheet(self) -> SheetPos {
+        SheetPos(self.0 - SHEET_ORIGIN)
+    }
+
+    /// Height map cell under this position, if it's on the sheet
+    pub fn to_cell(self, hm: &HeightMap) -> Option<CellIdx> {
+        self.to_sheet().to_cell(hm)
+    }
+}
+
+impl SheetPos {
+    pub fn new(x: f32, y: f32, z: f32) -> Self {
+        SheetPos(Vec3::new(x, y, z))
+    }
+
+    pub fn to_world(self) -> WorldPos {
+        WorldPos(self.0 + SHEET_ORIGIN)
+    }
+
+    /// Height map cell under this position, if it's on the sheet
+    pub fn to_cell(self, hm: &HeightMap) -> Option<CellIdx> {
+        hm.get_cell_from_pos(self.0.x, self.0.z).map(CellIdx::from)
+    }
+
+    /// Where this is on the sheet, ignoring height
+    pub fn xz(self) -> Vec2 {
+        self.0.xz()
+    }
+
+    /// Height of the ground under this position
+    pub fn ground(self, hm: &HeightMap) -> Option<f32> {
+        hm.sample_height(self.0.x, self.0.z)
+    }
+}
+
+impl CellIdx {
+    pub fn new(x: usize, y: usize) -> Self {
+        CellIdx { x, y }
+    }
+
+    /// SHEET position of the cell's corner, on the ground
+    pub fn to_sheet(self, hm: &HeightMap) -> SheetPos {
+        let size = hm.cell_size();
+        SheetPos::new(self.x as f32 * size.x, hm.get(self.x, self.y), self.y as f32 * size.y)
+    }
+
+    pub fn to_world(self, hm: &HeightMap) -> WorldPos {
+        self.to_sheet(hm).to_world()
+    }
+}
+
+impl From<(usize, usize)> for CellIdx {
+    fn from((x, y): (usize, usize)) -> Self {
+        CellIdx { x, y }
+    }
+}
+
+impl From<CellIdx> for (usize, usize) {
+    fn from(c: CellIdx) -> Self {
+        (c.x, c.y)
+    }
+}
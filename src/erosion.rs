@@ -0,0 +1,202 @@
+use bevy::prelude::*;
+use rand::prelude::*;
+
+use crate::height_map::HeightMap;
+
+/// Tuning for `HeightMap::erode`. Heights are in metres, distances in cells.
+#[derive(Resource, Clone, Debug)]
+pub struct ErosionSettings {
+    pub enabled: bool,
+    pub margin: usize, // cells along the sides left alone, so the halfpipe walls stay
+
+    // Thermal: steep slopes slump until they are below the talus
+    pub thermal_iterations: usize,
+    pub talus: f32, // height difference to a neighbour that starts slumping
+    pub thermal_rate: f32, // fraction of the excess moved each iteration
+
+    // Hydraulic: raindrops roll downhill picking up and dropping sediment
+    pub droplets: usize,
+    pub droplet_lifetime: usize, // max steps a droplet takes
+    pub inertia: f32, // 0: always go straight downhill, 1: never turn
+    pub capacity: f32, // sediment a droplet can carry, per speed and water
+    pub min_capacity_slope: f32,
+    pub erode_rate: f32,
+    pub deposit_rate: f32,
+    pub evaporation: f32,
+    pub gravity: f32,
+}
+
+impl Default for ErosionSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            margin: 10,
+            thermal_iterations: 10,
+            talus: 1.5,
+            thermal_rate: 0.25,
+            droplets: 40_000,
+            droplet_lifetime: 40,
+            inertia: 0.3,
+            capacity: 4.0,
+            min_capacity_slope: 0.01,
+            erode_rate: 0.3,
+            deposit_rate: 0.3,
+            evaporation: 0.02,
+            gravity: 4.0,
+        }
+    }
+}
+
+impl HeightMap {
+    /// Thermal then hydraulic erosion. Always the same result for the same
+    /// seed and settings. Changes aren't tracked as dirty or paid for with soil.
+    /// Only the rows still held are eroded (see `drop_rows`), with y counted
+    /// from the first of them.
+    pub fn erode(&mut self, settings: &ErosionSettings) {
+        if !settings.enabled {
+            return;
+        }
+        self.erode_thermal(settings);
+        self.erode_hydraulic(settings);
+    }
+
+    fn erode_thermal(&mut self, settings: &ErosionSettings) {
+        let (w, h) = (self.cell_w, self.cell_h - self.first_row());
+        let sides = settings.margin..w.saturating_sub(settings.margin);
+        let mut moved = vec![0.0; w * h];
+
+        for _ in 0..settings.thermal_iterations {
+            let heights = self.heights();
+            moved.fill(0.0);
+            for y in 0..h {
+                for x in sides.clone() {
+                    let i = y * w + x;
+                    let cur = heights[i];
+
+                    // Slump towards the lowest neighbour
+                    let mut lowest = None;
+                    let mut max_diff = settings.talus;
+                    for (nx, ny) in [
+                        (x.wrapping_sub(1), y),
+                        (x + 1, y),
+                        (x, y.wrapping_sub(1)),
+                        (x, y + 1)
+                    ] {
+                        if !sides.contains(&nx) || ny >= h {
+                            continue;
+                        }
+                        let diff = cur - heights[ny * w + nx];
+                        if diff > max_diff {
+                            max_diff = diff;
+                            lowest = Some(ny * w + nx);
+                        }
+                    }
+                    if let Some(n) = lowest {
+                        let amount = (max_diff - settings.talus) * 0.5 * settings.thermal_rate;
+                        moved[i] -= amount;
+                        moved[n] += amount;
+                    }
+                }
+            }
+            for (h, m) in self.heights_mut().iter_mut().zip(&moved) {
+                *h = (*h + m).max(0.0);
+            }
+        }
+    }
+
+    fn erode_hydraulic(&mut self, settings: &ErosionSettings) {
+        let (w, h) = (self.cell_w, self.cell_h - self.first_row());
+        let (min_x, max_x) = (settings.margin as f32, w.saturating_sub(settings.margin + 1) as f32);
+        if min_x >= max_x || h < 2 {
+            return;
+        }
+        let mut rng = self.rng(4);
+        let heights = self.heights_mut();
+
+        for _ in 0..settings.droplets {
+            let mut pos = Vec2::new(
+                rng.random_range(min_x..max_x),
+                rng.random_range(0.0..(h - 1) as f32)
+            );
+            let mut dir = Vec2::ZERO;
+            let mut speed = 1.0;
+            let mut water = 1.0;
+            let mut sediment = 0.0;
+
+            for _ in 0..settings.droplet_lifetime {
+                let (height, grad) = height_and_gradient(heights, w, pos);
+
+                // Turn downhill, keeping some of the old direction
+                dir = dir * settings.inertia - grad * (1.0 - settings.inertia);
+                if dir.length_squared() < f32::EPSILON {
+                    // Flat: pick any way
+                    let a = rng.random_range(0.0..std::f32::consts::TAU);
+                    dir = Vec2::from_angle(a);
+                }
+                dir = dir.normalize();
+                let next = pos + dir;
+                if next.x < min_x || next.y < 0.0 ||
+                    next.x >= max_x || next.y >= (h - 1) as f32 {
+                    break;
+                }
+
+                let dh = height_and_gradient(heights, w, next).0 - height;
+                let capacity = (-dh).max(settings.min_capacity_slope)
+                    * speed * water * settings.capacity;
+
+                if dh > 0.0 || sediment > capacity {
+                    // Going uphill fills the hole behind, otherwise drop the excess
+                    let amount = if dh > 0.0 {
+                        dh.min(sediment)
+                    } else {
+                        (sediment - capacity) * settings.deposit_rate
+                    };
+                    sediment -= amount;
+                    spread(heights, w, pos, amount);
+                } else {
+                    // Never dig deeper than the step down, or it makes pits
+                    let amount = ((capacity - sediment) * settings.erode_rate).min(-dh);
+                    sediment += amount;
+                    spread(heights, w, pos, -amount);
+                }
+
+                speed = (speed * speed - dh * settings.gravity).max(0.0).sqrt();
+                water *= 1.0 - settings.evaporation;
+                pos = next;
+            }
+        }
+    }
+}
+
+/// Bilinear height and its slope (per cell) at a position in cells
+fn height_and_gradient(heights: &[f32], w: usize, pos: Vec2) -> (f32, Vec2) {
+    let (x, y) = (pos.x as usize, pos.y as usize);
+    let (tx, ty) = (pos.x - x as f32, pos.y - y as f32);
+    let i = y * w + x;
+    let (nw, ne, sw, se) = (heights[i], heights[i + 1], heights[i + w], heights[i + w + 1]);
+
+    let grad = Vec2::new(
+        (ne - nw) * (1.0 - ty) + (se - sw) * ty,
+        (sw - nw) * (1.0 - tx) + (se - ne) * tx
+    );
+    let height = nw * (1.0 - tx) * (1.0 - ty)
+        + ne * tx * (1.0 - ty)
+        + sw * (1.0 - tx) * ty
+        + se * tx * ty;
+    (height, grad)
+}
+
+/// Add `amount` to the four cells around a position in cells, weighted by closeness
+fn spread(heights: &mut [f32], w: usize, pos: Vec2, amount: f32) {
+    let (x, y) = (pos.x as usize, pos.y as usize);
+    let (tx, ty) = (pos.x - x as f32, pos.y - y as f32);
+    let i = y * w + x;
+    for (j, weight) in [
+        (i, (1.0 - tx) * (1.0 - ty)),
+        (i + 1, tx * (1.0 - ty)),
+        (i + w, (1.0 - tx) * ty),
+        (i + w + 1, tx * ty),
+    ] {
+        heights[j] = (heights[j] + amount * weight).max(0.0);
+    }
+}
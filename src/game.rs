@@ -16,17 +16,25 @@ use crate::constants::{
     TARGET_CENTRE,
     STONE_ANGULAR_DAMPENING_INC_START_AT,
     STONE_ANGULAR_DAMPENING_INC_AMOUNT,
-    STONE_HURL_AIM_ANGLE_MULTIPLIER, STONE_MAX_VEL, SHOW_DBG, STONE_Y
+    STONE_HURL_AIM_ANGLE_MULTIPLIER, STONE_MAX_VEL, SHOW_DBG, STONE_Y, STONE_Z,
+    STONES_PER_END,
 };
 
 use crate::camera::camera_plugin;
+use crate::color_ramp::color_ramp_plugin;
+use crate::height_map::HeightMap;
+use crate::impact::impact_plugin;
 use crate::player::{player_plugin, HurlStone};
 use crate::powerups::powerups_plugin;
-use crate::sheet::{sheet_plugin, StoneInHole};
+use crate::sheet::{sheet_plugin, SheetMode, SheetName, StoneInHole};
+use crate::spin::{spin_plugin, Spin};
 use crate::splash::splash_plugin;
-use crate::stone::{Stone, stone_plugin};
+use crate::stone::{ActiveStone, SpawnStone, Stone, stone_plugin};
+use crate::surface::surface_plugin;
+use crate::sweep::{sweep_plugin, Sweep};
 use crate::timey::Timey;
 use crate::townsfolk::townsfolk_plugin;
+use crate::water::{water_plugin, StoneSunk, Sunk};
 
 pub struct GamePlugin;
 
@@ -36,6 +44,10 @@ pub struct Spotty;
 #[derive(Component)]
 pub struct OnGameScreen;
 
+/// On things that only last one throw, they're cleared before the next
+#[derive(Component)]
+pub struct OnThrowScreen;
+
 #[derive(States, Debug, Clone, PartialEq, Eq, Hash, Default)]
 pub enum GameState {
     #[default]
@@ -70,9 +82,16 @@ pub struct BigThor;
 #[derive(Resource)]
 pub struct HiScore {
     pub score: f32,
+    pub endless: f32, // furthest distance in endless mode
     pub fault: bool
 }
 
+/// Stones thrown so far this end
+#[derive(Resource, Default)]
+pub struct Round {
+    pub thrown: usize,
+}
+
 #[derive(PhysicsLayer, Default)]
 pub enum CollisionLayer {
     #[default]
@@ -87,6 +106,31 @@ fn distance_to_target(pos: Vec3) -> f32 {
     pos.distance(TARGET_CENTRE)
 }
 
+/// Endless sheets have no target, it's how far the stone got
+fn distance_travelled(pos: Vec3) -> f32 {
+    (pos.z - STONE_Z).max(0.0)
+}
+
+/// Endless drops the sheet behind the stone as it goes, so there's
+/// nowhere left for a second stone to start from
+fn stones_per_end(mode: SheetMode) -> usize {
+    match mode {
+        SheetMode::Classic => STONES_PER_END,
+        SheetMode::Endless => 1,
+    }
+}
+
+/// Best of the stones' distances: closest to the target, or furthest
+/// in endless. Sunk stones don't count, None if they all sank.
+pub fn best_distance(stones: impl IntoIterator<Item = (f32, bool)>, endless: bool) -> Option<f32> {
+    let dists = stones.into_iter().filter(|(_, sunk)| !sunk).map(|(d, _)| d);
+    if endless {
+        dists.reduce(f32::max)
+    } else {
+        dists.reduce(f32::min)
+    }
+}
+
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
         // Library plugins
@@ -99,20 +143,28 @@ impl Plugin for GamePlugin {
         // Game plugins
         app.add_plugins((
             camera_plugin,
+            color_ramp_plugin,
+            impact_plugin,
             player_plugin,
             powerups_plugin,
             sheet_plugin,
+            spin_plugin,
             splash_plugin,
             stone_plugin,
-            townsfolk_plugin
+            surface_plugin,
+            sweep_plugin,
+            townsfolk_plugin,
+            water_plugin
         ));
 
-        app.insert_resource(HiScore { score: 2000.0, fault: false });
+        app.insert_resource(HiScore { score: 2000.0, endless: 0.0, fault: false });
+        app.init_resource::<Round>();
         app.init_state::<GameState>()
             .add_sub_state::<GamePhase>();
 
         // Systems
         app.add_systems(OnEnter(GameState::InGame), setup);
+        app.add_systems(OnEnter(GamePhase::Aiming), setup_throw);
         app.add_systems(OnEnter(GamePhase::Sculpting), fire_stone);
         app.add_systems(OnEnter(GamePhase::StoneStopped), on_stone_stopped_enter);
         app.add_systems(
@@ -126,12 +178,17 @@ impl Plugin for GamePlugin {
                 text_distance,
                 text_power,
             ));
-        app.add_systems(OnExit(GameState::InGame), despawn_screen::<OnGameScreen>);
+        app.add_systems(OnExit(GamePhase::StoneStopped), despawn_screen::<OnThrowScreen>);
+        app.add_systems(OnExit(GameState::InGame), (
+            despawn_screen::<OnGameScreen>,
+            despawn_screen::<OnThrowScreen>,
+        ));
 
         // Triggers
         app.add_observer(on_hurl_stone);
         app.add_observer(start_anims_on_load);
         app.add_observer(on_stone_in_hole);
+        app.add_observer(on_stone_sunk);
     }
 }
 
@@ -145,6 +202,7 @@ fn setup(
 ) {
     // Reset cheat check
     hi.fault = false;
+    commands.insert_resource(Round::default());
 
     // Thor plane
     let texture_handle = asset_server.load("thor.png");
@@ -227,13 +285,6 @@ fn setup(
         OnGameScreen
     ));
 
-    // Auto fire when aiming (take too long)
-    commands.spawn((
-        Timey::new(25.0),
-        AimTooSlowTimer,
-        OnGameScreen
-    ));
-
     if SHOW_DBG {
         commands.spawn((
             TextFont {
@@ -334,9 +385,38 @@ fn setup(
 
 }
 
+/// Every throw gets its own aim timer, and says which stone it is
+fn setup_throw(
+    mut commands: Commands,
+    round: Res<Round>,
+    mode: Res<SheetMode>,
+) {
+    // Auto fire when aiming (take too long)
+    commands.spawn((
+        Timey::new(25.0),
+        AimTooSlowTimer,
+        OnThrowScreen
+    ));
+
+    commands.spawn((
+        TextFont {
+            font_size: 18.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(81.0),
+            left: Val::Px(5.0),
+            ..default()
+        },
+        OnThrowScreen,
+    ))
+        .with_child( Text::new("Stone:"))
+        .with_child( Text::new(format!(" {}/{}", round.thrown + 1, stones_per_end(*mode))));
+}
 
 fn fire_stone(
-    stone: Query<Entity, With<Stone>>,
+    stone: Query<Entity, With<ActiveStone>>,
     mut commands: Commands
 ) {
     let Ok(e) = stone.get_single() else { return; };
@@ -344,7 +424,7 @@ fn fire_stone(
 }
 
 pub fn track_and_dampen_stone(
-    mut stone: Query<(&Transform, &LinearVelocity, &mut AngularDamping), With<Stone>>,
+    mut stone: Query<(&Transform, &LinearVelocity, &mut AngularDamping), With<ActiveStone>>,
     mut phase: ResMut<NextState<GamePhase>>,
     time: Res<Time>
 ){
@@ -388,35 +468,48 @@ fn on_stone_in_hole (
     phase.set(GamePhase::StoneStopped);
 }
 
+fn on_stone_sunk(
+    _trigger: Trigger<StoneSunk>,
+    mut phase: ResMut<NextState<GamePhase>>,
+) {
+    phase.set(GamePhase::StoneStopped);
+}
+
 fn on_hurl_stone(
     trigger: Trigger<HurlStone>,
     mut phase: ResMut<NextState<GamePhase>>,
-    mut stone: Query<&mut LinearVelocity, With<Stone>>
+    mut stone: Query<(&mut LinearVelocity, &mut AngularVelocity, &mut Spin), With<ActiveStone>>,
 ) {
-    let Ok(mut vel) = stone.get_single_mut() else { return; };
+    let Ok((mut vel, mut ang, mut spin)) = stone.get_single_mut() else { return; };
+    *spin = Spin(trigger.event().spin);
     vel.x = trigger.event().angle * STONE_HURL_AIM_ANGLE_MULTIPLIER;
     vel.z = trigger.event().power * STONE_MAX_VEL;
     vel.y = -100.0;
-    info!("power: {} angle: {}", vel.z, vel.x);
+    ang.0 += spin.angular();
+    info!("power: {} angle: {} spin: {}", vel.z, vel.x, spin.0);
     phase.set(GamePhase::Sculpting);
 }
 
 
 fn text_distance(
     mut txt: Query<&mut Text, With<TextDistance>>,
-    stone: Query<&Transform, With<Stone>>
+    stone: Query<&Transform, With<ActiveStone>>,
+    mode: Res<SheetMode>,
 ) {
     let Ok(stone_pos) = stone.get_single() else { return; };
 
     for mut span in txt.iter_mut() {
-        let vtxt = distance_to_target(stone_pos.translation);
+        let vtxt = match *mode {
+            SheetMode::Classic => distance_to_target(stone_pos.translation),
+            SheetMode::Endless => distance_travelled(stone_pos.translation),
+        };
         span.0 = format!("{vtxt:.2}");
     }
 }
 
 fn text_power(
     mut txt: Query<&mut Text, With<TextPower>>,
-    stone: Query<&LinearVelocity, With<Stone>>
+    stone: Query<&LinearVelocity, With<ActiveStone>>
 ) {
     let Ok(vel) = stone.get_single() else { return; };
 
@@ -442,22 +535,57 @@ fn start_anims_on_load(
     }*/
 }
 
+/// The active stone has stopped. Throw the next one, or once they've
+/// all gone score the end on the best stone left on the sheet.
 fn on_stone_stopped_enter(
     mut cmds: Commands,
-    stone: Query<(Entity, &Transform), With<Stone>>,
+    active: Query<Entity, With<ActiveStone>>,
+    stones: Query<(Entity, &Transform, Has<Sunk>), With<Stone>>,
+    height_map: Res<HeightMap>,
+    sheet_name: Res<SheetName>,
+    mode: Res<SheetMode>,
+    sweep: Res<Sweep>,
+    mut round: ResMut<Round>,
+    mut phase: ResMut<NextState<GamePhase>>,
     mut hi: ResMut<HiScore>
 ) {
-    let mut dist: f32 = 999.0;
-    if let Ok((e, st)) = stone.get_single() {
+    // It stays on the sheet for the next stones to hit
+    if let Ok(e) = active.get_single() {
+        cmds.entity(e).remove::<ActiveStone>();
+    }
+    round.thrown += 1;
+    if round.thrown < stones_per_end(*mode) {
+        cmds.trigger(SpawnStone);
+        phase.set(GamePhase::Aiming);
+        return;
+    }
+
+    let endless = *mode == SheetMode::Endless;
+    let mut dists = vec![];
+    for (e, st, sunk) in stones.iter() {
         cmds.entity(e).remove::<RigidBody>();
-        dist = distance_to_target(st.translation);
+        let dist = if endless {
+            distance_travelled(st.translation)
+        } else {
+            distance_to_target(st.translation)
+        };
+        dists.push((dist, sunk));
     }
+    let best = best_distance(dists, endless);
+    let is_sunk = best.is_none();
+    let dist = best.unwrap_or(if endless { 0.0 } else { 999.0 });
 
-    let hiscore = hi.score;
+    // Closest to the hole wins, or furthest in endless
+    let hiscore = if endless { hi.endless } else { hi.score };
     let is_fault = hi.fault;
-    let is_hi = !is_fault && dist < hiscore;
+    // Nothing counts if every stone sank
+    let is_hi = !is_fault && !is_sunk && if endless { dist > hiscore } else { dist < hiscore };
     if is_hi {
-        hi.score = dist;
+        if endless {
+            hi.endless = dist;
+        } else {
+            hi.score = dist;
+        }
     }
 
     cmds.spawn((
@@ -476,6 +604,8 @@ fn on_stone_stopped_enter(
         .with_child(
             if is_fault {
                 Text::new("CheaT:")
+            } else if is_sunk {
+                Text::new("SuNK:")
             } else {
                 Text::new("OVeR:")
             })
@@ -499,13 +629,64 @@ fn on_stone_stopped_enter(
     ))
         .with_child( Text::new("Best:"))
         .with_child(
-            if is_hi {
+            if is_hi && endless {
+                Text::new("NEW HI SCORE!")
+            } else if is_hi {
                 Text::new("NEW LO SCORE!")
             } else {
                 Text::new(format!("{hiscore:.2}"))
             }
         );
 
+    cmds.spawn((
+        TextFont {
+            font_size: 24.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(70.0),
+            left: Val::Percent(50.0),
+            ..default()
+        },
+        OnGameScreen,
+    ))
+        .with_child( Text::new("Seed:"))
+        .with_child( Text::new(format!("{}", height_map.seed)));
+
+    cmds.spawn((
+        TextFont {
+            font_size: 24.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(75.0),
+            left: Val::Percent(50.0),
+            ..default()
+        },
+        OnGameScreen,
+    ))
+        .with_child( Text::new("Sheet:"))
+        .with_child( Text::new(sheet_name.0.clone()));
+
+    // Sweeping is fair play, it's only reported
+    cmds.spawn((
+        TextFont {
+            font_size: 24.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(80.0),
+            left: Val::Percent(50.0),
+            ..default()
+        },
+        OnGameScreen,
+    ))
+        .with_child( Text::new("Swept:"))
+        .with_child( Text::new(format!("{:.1}s", sweep.swept)));
+
     cmds.spawn((
         Timey::new(20.0),
         StoneStoppedTimer,
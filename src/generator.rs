@@ -0,0 +1,497 @@
+use bevy::prelude::*;
+use noise::{NoiseFn, Perlin};
+use rand::prelude::*;
+
+use crate::constants::MAX_TERRAIN_HEIGHT;
+use crate::height_map::HeightMap;
+use crate::stamp::{Stamp, StampShape};
+
+/// A recipe for the heights of a sheet.
+pub trait TerrainGenerator: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Tunable values, by name
+    fn params(&mut self) -> Vec<(&'static str, &mut f32)>;
+
+    /// Height of cell x/y on a `cell_w` x `cell_h` sheet
+    fn height(&self, noise: &Perlin, x: usize, y: usize, cell_w: usize, cell_h: usize) -> f32;
+
+    /// Features to stamp on once the heights are set, centred on a cell
+    fn stamps(&self, _hm: &HeightMap) -> Vec<(Stamp, (usize, usize))> {
+        vec![]
+    }
+
+    /// Whether erosion should run over the result
+    fn erodes(&self) -> bool {
+        true
+    }
+
+    /// Set a param by name. Returns false if there isn't one.
+    fn set_param(&mut self, name: &str, value: f32) -> bool {
+        match self.params().into_iter().find(|(n, _)| *n == name) {
+            Some((_, v)) => {
+                *v = value;
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+/// Walls up the sides, from -1 (right) to 1 (left) across the sheet
+fn halfpipe(x: usize, cell_w: usize, height: f32) -> f32 {
+    let px = ((x as f32 / cell_w as f32) - 0.5) * 2.0;
+    px.powf(12.0) * height
+}
+
+/// Scale for the terrain along the sheet: starts flat, goes bumpy, ends flat.
+// Curve: 1 - ((x / 0.4) - 1.25) ^ 4
+fn z_curve(y: usize, cell_h: usize) -> f32 {
+    let z_percent = y as f32 / cell_h as f32;
+    let curve = 1.0 - ((z_percent / 0.48) - 1.0).powf(4.0);
+    curve.max(0.0) // Clip floor
+}
+
+fn noise_at(noise: &Perlin, x: usize, y: usize, size: f32, z: f64) -> f32 {
+    noise.get([x as f64 * size as f64, y as f64 * size as f64, z]) as f32
+}
+
+/// The original sheet: rolling hills and small bumps in the low bits
+pub struct Classic {
+    pub main_size: f32,
+    pub bump_size: f32,
+    pub bump_height: f32,
+    pub hill_height: f32,
+    pub wall_height: f32,
+}
+
+impl Default for Classic {
+    fn default() -> Self {
+        Self {
+            main_size: 0.01,
+            bump_size: 0.2,
+            bump_height: 2.5,
+            hill_height: MAX_TERRAIN_HEIGHT,
+            wall_height: 50.0,
+        }
+    }
+}
+
+impl TerrainGenerator for Classic {
+    fn name(&self) -> &'static str {
+        "classic"
+    }
+
+    fn params(&mut self) -> Vec<(&'static str, &mut f32)> {
+        vec![
+            ("main_size", &mut self.main_size),
+            ("bump_size", &mut self.bump_size),
+            ("bump_height", &mut self.bump_height),
+            ("hill_height", &mut self.hill_height),
+            ("wall_height", &mut self.wall_height),
+        ]
+    }
+
+    fn height(&self, noise: &Perlin, x: usize, y: usize, cell_w: usize, cell_h: usize) -> f32 {
+        let noise_val = noise_at(noise, x, y, self.main_size, 0.0);
+        let bump_val = noise_at(noise, x, y, self.bump_size, 0.0) * 0.5;
+
+        let noise_height = noise_val.max(0.0) * self.hill_height;
+        let bump_height = bump_val * self.bump_height;
+
+        let height = noise_height + halfpipe(x, cell_w, self.wall_height);
+        let bump_ratio = (1.0 - (height / (self.hill_height * 0.2))).max(0.0);
+        let slope = z_curve(y, cell_h);
+
+        ((height * slope) + (bump_height * bump_ratio)).max(0.0)
+    }
+}
+
+/// Rows of regular bumps to bounce over
+pub struct Moguls {
+    pub spacing: f32, // cells between mogul tops
+    pub mogul_height: f32,
+    pub jitter: f32, // how far the moguls wander off the grid, in cells
+    pub wall_height: f32,
+}
+
+impl Default for Moguls {
+    fn default() -> Self {
+        Self {
+            spacing: 14.0,
+            mogul_height: 3.0,
+            jitter: 4.0,
+            wall_height: 50.0,
+        }
+    }
+}
+
+impl TerrainGenerator for Moguls {
+    fn name(&self) -> &'static str {
+        "moguls"
+    }
+
+    fn params(&mut self) -> Vec<(&'static str, &mut f32)> {
+        vec![
+            ("spacing", &mut self.spacing),
+            ("mogul_height", &mut self.mogul_height),
+            ("jitter", &mut self.jitter),
+            ("wall_height", &mut self.wall_height),
+        ]
+    }
+
+    fn height(&self, noise: &Perlin, x: usize, y: usize, cell_w: usize, cell_h: usize) -> f32 {
+        let jx = noise_at(noise, x, y, 0.05, 0.0) * self.jitter;
+        let jy = noise_at(noise, x, y, 0.05, 1.0) * self.jitter;
+        let fx = (x as f32 + jx) / self.spacing * std::f32::consts::TAU;
+        let fy = (y as f32 + jy) / self.spacing * std::f32::consts::TAU;
+        let mogul = (fx.sin() * fy.sin()).max(0.0) * self.mogul_height;
+
+        mogul * z_curve(y, cell_h) + halfpipe(x, cell_w, self.wall_height)
+    }
+
+    fn stamps(&self, hm: &HeightMap) -> Vec<(Stamp, (usize, usize))> {
+        // A few craters to fall in
+        let mut rng = hm.rng(5);
+        (0..6)
+            .map(|_| {
+                let x = rng.random_range(hm.cell_w / 4..hm.cell_w * 3 / 4);
+                let y = rng.random_range(hm.cell_h / 5..hm.cell_h * 4 / 5);
+                let size = rng.random_range(5.0..10.0);
+                (Stamp::new(StampShape::Crater, Vec2::splat(size), size * 0.4), (x, y))
+            })
+            .collect()
+    }
+}
+
+/// A winding valley with high walls either side
+pub struct Canyon {
+    pub width: f32, // fraction of the sheet
+    pub depth: f32,
+    pub wander: f32, // fraction of the sheet the centre moves
+    pub wander_size: f32,
+    pub wall_height: f32,
+}
+
+impl Default for Canyon {
+    fn default() -> Self {
+        Self {
+            width: 0.25,
+            depth: 30.0,
+            wander: 0.25,
+            wander_size: 0.008,
+            wall_height: 50.0,
+        }
+    }
+}
+
+impl TerrainGenerator for Canyon {
+    fn name(&self) -> &'static str {
+        "canyon"
+    }
+
+    fn params(&mut self) -> Vec<(&'static str, &mut f32)> {
+        vec![
+            ("width", &mut self.width),
+            ("depth", &mut self.depth),
+            ("wander", &mut self.wander),
+            ("wander_size", &mut self.wander_size),
+            ("wall_height", &mut self.wall_height),
+        ]
+    }
+
+    fn height(&self, noise: &Perlin, x: usize, y: usize, cell_w: usize, cell_h: usize) -> f32 {
+        // Centre of the canyon drifts across the sheet, but starts in the middle
+        let slope = z_curve(y, cell_h);
+        let drift = noise.get([y as f64 * self.wander_size as f64, 0.5, 2.0]) as f32;
+        let centre = 0.5 + drift * self.wander * slope;
+
+        let px = x as f32 / cell_w as f32;
+        let d = ((px - centre).abs() / (self.width * 0.5)).min(1.0);
+        let wall = d * d * (3.0 - 2.0 * d); // smoothstep
+        let rough = noise_at(noise, x, y, 0.1, 3.0).max(0.0) * 2.0;
+
+        wall * self.depth * slope.max(0.3) + rough * slope + halfpipe(x, cell_w, self.wall_height)
+    }
+}
+
+/// The classic hills cut into flat steps
+pub struct Terraces {
+    pub step_height: f32,
+    pub sharpness: f32, // 0 is the plain hills, 1 flat steps
+    pub hill_height: f32,
+    pub wall_height: f32,
+}
+
+impl Default for Terraces {
+    fn default() -> Self {
+        Self {
+            step_height: 3.0,
+            sharpness: 0.85,
+            hill_height: MAX_TERRAIN_HEIGHT * 1.5,
+            wall_height: 50.0,
+        }
+    }
+}
+
+impl TerrainGenerator for Terraces {
+    fn name(&self) -> &'static str {
+        "terraces"
+    }
+
+    fn params(&mut self) -> Vec<(&'static str, &mut f32)> {
+        vec![
+            ("step_height", &mut self.step_height),
+            ("sharpness", &mut self.sharpness),
+            ("hill_height", &mut self.hill_height),
+            ("wall_height", &mut self.wall_height),
+        ]
+    }
+
+    fn height(&self, noise: &Perlin, x: usize, y: usize, cell_w: usize, cell_h: usize) -> f32 {
+        let hill = noise_at(noise, x, y, 0.012, 0.0).max(0.0) * self.hill_height * z_curve(y, cell_h);
+        let step = (hill / self.step_height).floor() * self.step_height;
+        let h = hill + (step - hill) * self.sharpness.clamp(0.0, 1.0);
+
+        h + halfpipe(x, cell_w, self.wall_height)
+    }
+
+    fn erodes(&self) -> bool {
+        false // keep the steps crisp
+    }
+}
+
+/// Nothing but the halfpipe, for practicing sculpting
+pub struct FlatPractice {
+    pub wall_height: f32,
+}
+
+impl Default for FlatPractice {
+    fn default() -> Self {
+        Self {
+            wall_height: 50.0,
+        }
+    }
+}
+
+impl TerrainGenerator for FlatPractice {
+    fn name(&self) -> &'static str {
+        "flat practice"
+    }
+
+    fn params(&mut self) -> Vec<(&'static str, &mut f32)> {
+        vec![
+            ("wall_height", &mut self.wall_height),
+        ]
+    }
+
+    fn height(&self, _noise: &Perlin, x: usize, _y: usize, cell_w: usize, _cell_h: usize) -> f32 {
+        halfpipe(x, cell_w, self.wall_height)
+    }
+
+    fn erodes(&self) -> bool {
+        false
+    }
+}
+
+/// A long ramp up to a kicker, then a drop to land in
+pub struct SkiJump {
+    pub start: f32, // fraction along the sheet the ramp starts
+    pub length: f32, // fraction of the sheet the ramp takes
+    pub jump_height: f32,
+    pub lip: f32, // extra kick at the end of the ramp
+    pub wall_height: f32,
+}
+
+impl Default for SkiJump {
+    fn default() -> Self {
+        Self {
+            start: 0.2,
+            length: 0.2,
+            jump_height: 25.0,
+            lip: 4.0,
+            wall_height: 50.0,
+        }
+    }
+}
+
+impl TerrainGenerator for SkiJump {
+    fn name(&self) -> &'static str {
+        "ski jump"
+    }
+
+    fn params(&mut self) -> Vec<(&'static str, &mut f32)> {
+        vec![
+            ("start", &mut self.start),
+            ("length", &mut self.length),
+            ("jump_height", &mut self.jump_height),
+            ("lip", &mut self.lip),
+            ("wall_height", &mut self.wall_height),
+        ]
+    }
+
+    fn height(&self, noise: &Perlin, x: usize, y: usize, cell_w: usize, cell_h: usize) -> f32 {
+        let t = (y as f32 / cell_h as f32 - self.start) / self.length;
+        let ramp = if (0.0..=1.0).contains(&t) {
+            // Gentle at first, steepening towards the kicker
+            t * t * self.jump_height
+        } else {
+            0.0
+        };
+        let bumps = noise_at(noise, x, y, 0.02, 0.0).max(0.0) * MAX_TERRAIN_HEIGHT * 0.5;
+        let after = if t > 1.0 { bumps * z_curve(y, cell_h) } else { 0.0 };
+
+        ramp + after + halfpipe(x, cell_w, self.wall_height)
+    }
+
+    fn stamps(&self, hm: &HeightMap) -> Vec<(Stamp, (usize, usize))> {
+        let x = hm.cell_w / 2;
+        let end = ((self.start + self.length) * hm.cell_h as f32) as usize;
+        let size = Vec2::new(hm.cell_w as f32 * 0.3, 6.0);
+        vec![
+            // Lip on the end of the ramp
+            (Stamp::new(StampShape::Kicker, size, self.lip), (x, end.saturating_sub(6))),
+            // Landing slope, highest next to the jump
+            (
+                Stamp::new(StampShape::Ramp, Vec2::new(size.x, 25.0), self.jump_height * 0.5)
+                    .facing(Vec2::NEG_Y),
+                (x, end + 30)
+            ),
+        ]
+    }
+
+    fn erodes(&self) -> bool {
+        false
+    }
+}
+
+/// Classic hills that never end, for streaming. Only depends on the
+/// cell, never the sheet length, so every chunk joins up with the last.
+pub struct Endless {
+    pub main_size: f32,
+    pub bump_size: f32,
+    pub bump_height: f32,
+    pub hill_height: f32, // at the start, growing with distance
+    pub hill_growth: f32, // extra hill height per row
+    pub max_hill_height: f32,
+    pub run_in: f32, // rows of flat at the start
+    pub wall_height: f32,
+}
+
+impl Default for Endless {
+    fn default() -> Self {
+        Self {
+            main_size: 0.01,
+            bump_size: 0.2,
+            bump_height: 2.5,
+            hill_height: MAX_TERRAIN_HEIGHT * 0.5,
+            hill_growth: 0.002,
+            max_hill_height: MAX_TERRAIN_HEIGHT * 2.0,
+            run_in: 200.0,
+            wall_height: 50.0,
+        }
+    }
+}
+
+impl TerrainGenerator for Endless {
+    fn name(&self) -> &'static str {
+        "endless"
+    }
+
+    fn params(&mut self) -> Vec<(&'static str, &mut f32)> {
+        vec![
+            ("main_size", &mut self.main_size),
+            ("bump_size", &mut self.bump_size),
+            ("bump_height", &mut self.bump_height),
+            ("hill_height", &mut self.hill_height),
+            ("hill_growth", &mut self.hill_growth),
+            ("max_hill_height", &mut self.max_hill_height),
+            ("run_in", &mut self.run_in),
+            ("wall_height", &mut self.wall_height),
+        ]
+    }
+
+    fn height(&self, noise: &Perlin, x: usize, y: usize, cell_w: usize, _cell_h: usize) -> f32 {
+        let hill_height = (self.hill_height + y as f32 * self.hill_growth).min(self.max_hill_height);
+        let noise_val = noise_at(noise, x, y, self.main_size, 0.0);
+        let bump_val = noise_at(noise, x, y, self.bump_size, 0.0) * 0.5;
+
+        let noise_height = noise_val.max(0.0) * hill_height;
+        let bump_ratio = (1.0 - (noise_height / (hill_height * 0.2))).max(0.0);
+        let slope = (y as f32 / self.run_in.max(1.0)).min(1.0);
+
+        (noise_height + bump_val * self.bump_height * bump_ratio) * slope
+            + halfpipe(x, cell_w, self.wall_height)
+    }
+
+    fn erodes(&self) -> bool {
+        false // can't erode across the chunk joins
+    }
+}
+
+/// Every generator, and the one used for the next sheet.
+#[derive(Resource)]
+pub struct SheetGenerators {
+    pub generators: Vec<Box<dyn TerrainGenerator>>,
+    pub current: usize,
+    pub rotate: bool, // move on to the next generator every round, see `from_spec`
+}
+
+impl Default for SheetGenerators {
+    fn default() -> Self {
+        Self {
+            generators: vec![
+                Box::new(Classic::default()),
+                Box::new(Moguls::default()),
+                Box::new(Canyon::default()),
+                Box::new(Terraces::default()),
+                Box::new(FlatPractice::default()),
+                Box::new(SkiJump::default()),
+            ],
+            current: 0,
+            rotate: false,
+        }
+    }
+}
+
+impl SheetGenerators {
+    /// From a spec like `canyon` or `canyon:depth=40,width=0.3`, or
+    /// `rotate` to take turns starting with classic.
+    /// An unknown name keeps the default.
+    pub fn from_spec(spec: &str) -> Self {
+        let mut gens = SheetGenerators::default();
+        if spec.trim() == "rotate" {
+            gens.rotate = true;
+            return gens;
+        }
+        let (name, params) = spec.split_once(':').unwrap_or((spec, ""));
+        let Some(i) = gens.generators.iter().position(|g| g.name() == name.trim()) else {
+            warn!("no sheet generator called {}", name);
+            return gens;
+        };
+        gens.current = i;
+
+        for param in params.split(',').filter(|p| !p.is_empty()) {
+            let parsed = param
+                .split_once('=')
+                .and_then(|(k, v)| Some((k.trim(), v.trim().parse().ok()?)));
+            match parsed {
+                Some((k, v)) if gens.current_mut().set_param(k, v) => {},
+                _ => warn!("bad sheet param {}", param),
+            }
+        }
+        gens
+    }
+
+    pub fn current(&self) -> &dyn TerrainGenerator {
+        self.generators[self.current].as_ref()
+    }
+
+    pub fn current_mut(&mut self) -> &mut dyn TerrainGenerator {
+        self.generators[self.current].as_mut()
+    }
+
+    pub fn next(&mut self) {
+        self.current = (self.current + 1) % self.generators.len();
+    }
+}
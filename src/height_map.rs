@@ -1,8 +1,43 @@
 use bevy::prelude::*;
-use noise::{NoiseFn, Perlin};
-use crate::constants::{MAX_TERRAIN_HEIGHT, CELL_SIZE};
+use noise::Perlin;
+use crate::generator::{Classic, TerrainGenerator};
+use crate::zone::{Zone, ZoneRule};
 use rand::prelude::*;
 
+/// Inclusive range of cells that changed since the meshes were last synced.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DirtyRect {
+    pub min_x: usize,
+    pub min_y: usize,
+    pub max_x: usize,
+    pub max_y: usize,
+}
+
+impl DirtyRect {
+    pub fn cell(x: usize, y: usize) -> Self {
+        DirtyRect { min_x: x, min_y: y, max_x: x, max_y: y }
+    }
+
+    pub fn union(&self, other: &DirtyRect) -> Self {
+        DirtyRect {
+            min_x: self.min_x.min(other.min_x),
+            min_y: self.min_y.min(other.min_y),
+            max_x: self.max_x.max(other.max_x),
+            max_y: self.max_y.max(other.max_y),
+        }
+    }
+
+    /// Grow by `n` cells on every side
+    pub fn grow(&self, n: usize) -> Self {
+        DirtyRect {
+            min_x: self.min_x.saturating_sub(n),
+            min_y: self.min_y.saturating_sub(n),
+            max_x: self.max_x + n,
+            max_y: self.max_y + n,
+        }
+    }
+}
+
 #[derive(Resource, Clone, Debug)]
 pub struct HeightMap {
     pub w: f32,
@@ -11,69 +46,135 @@ pub struct HeightMap {
     pub cell_h: usize,
     rat_w: f32,
     rat_h: f32,
-    pub map: Vec<Vec<f32>>,
+    pub seed: u32,
+    pub soil: Option<f32>, // volume available to sculpt with. `None` is unlimited
+    pub zones: Vec<Zone>, // protected from sculpting
+    first_row: usize, // rows before this have been dropped, see `drop_rows`
+    map: Vec<f32>, // cell_w * (cell_h - first_row) heights, row by row
+    dirty: Option<DirtyRect>,
 }
 
 impl HeightMap {
-    pub fn new(w: f32, h: f32, cell_w: usize, cell_h: usize) -> Self {
+    /// The classic sheet
+    pub fn new(w: f32, h: f32, cell_w: usize, cell_h: usize, seed: u32) -> Self {
+        HeightMap::generate(w, h, cell_w, cell_h, seed, &Classic::default())
+    }
+
+    pub fn generate(
+        w: f32,
+        h: f32,
+        cell_w: usize,
+        cell_h: usize,
+        seed: u32,
+        generator: &dyn TerrainGenerator
+    ) -> Self {
+        let mut hm = HeightMap::flat(w, h, cell_w, cell_h, seed);
+        hm.terraform(generator);
+        for (stamp, cell) in generator.stamps(&hm) {
+            hm.apply_stamp(&stamp, cell);
+        }
+        // It's all new, nothing to sync yet
+        hm.take_dirty();
+        hm
+    }
+
+    /// A height map with every cell at zero
+    pub fn flat(w: f32, h: f32, cell_w: usize, cell_h: usize, seed: u32) -> Self {
         let rat_w = w / cell_w as f32;
         let rat_h = h / cell_h as f32;
-        let map = vec![vec![0.0; cell_w]; cell_h];
+        let map = vec![0.0; cell_w * cell_h];
 
-        let mut hm = HeightMap {
+        HeightMap {
             w,
             h,
             cell_w,
             cell_h,
             rat_w,
             rat_h,
+            seed,
+            soil: None,
+            zones: vec![],
+            first_row: 0,
             map,
-        };
-        hm.terraform();
-        hm
+            dirty: None,
+        }
+    }
+
+    /// Height of SHEET cell x/y. Dropped rows read as the first row still held.
+    pub fn get(&self, x: usize, y: usize) -> f32 {
+        let y = y.max(self.first_row) - self.first_row;
+        self.map[y * self.cell_w + x]
+    }
+
+    /// Size of a cell in metres, across and along the sheet
+    pub fn cell_size(&self) -> Vec2 {
+        Vec2::new(self.rat_w, self.rat_h)
+    }
+
+    /// First row still held. Zero unless the sheet is streamed.
+    pub fn first_row(&self) -> usize {
+        self.first_row
+    }
+
+    /// All the heights held, row by row from `first_row`
+    pub fn heights(&self) -> &[f32] {
+        &self.map
     }
 
-    pub fn terraform(&mut self) {
-        let mut rng = rand::rng();
-        let noise = Perlin::new(rng.next_u32());
-        let main_size = 0.01;
-        let bump_size = 0.2;
+    /// All the heights, row by row. Changes made
+    /// here aren't tracked as dirty or paid for with soil.
+    pub fn heights_mut(&mut self) -> &mut [f32] {
+        &mut self.map
+    }
+
+    pub fn mark_dirty(&mut self, rect: DirtyRect) {
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => dirty.union(&rect),
+            None => rect,
+        });
+    }
+
+    /// Cells changed since this was last called
+    pub fn take_dirty(&mut self) -> Option<DirtyRect> {
+        self.dirty.take()
+    }
+
+    /// Set every cell from a generator
+    pub fn terraform(&mut self, generator: &dyn TerrainGenerator) {
+        let noise = Perlin::new(self.seed);
+        for y in self.first_row..self.cell_h {
+            for x in 0..self.cell_w {
+                let h = generator.height(&noise, x, y, self.cell_w, self.cell_h);
+                self.map[(y - self.first_row) * self.cell_w + x] = h.max(0.0);
+            }
+        }
+    }
 
-        for y in 0..self.cell_h {
+    /// Add `rows` more rows to the end of the sheet from a generator.
+    /// Only joins up with the rows before if the generator ignores `cell_h`.
+    pub fn extend(&mut self, rows: usize, generator: &dyn TerrainGenerator) {
+        let noise = Perlin::new(self.seed);
+        let start = self.cell_h;
+        self.cell_h += rows;
+        self.h = self.cell_h as f32 * self.rat_h;
+        self.map.reserve(rows * self.cell_w);
+        for y in start..self.cell_h {
             for x in 0..self.cell_w {
-                let noise_val  = noise.get([
-                    x as f64 * main_size,
-                    y as f64 * main_size,
-                    0.0,
-                ]);
-                let bump_val  = noise.get([
-                    x as f64 * bump_size,
-                    y as f64 * bump_size,
-                    0.0,
-                ]) * 0.5;
-
-                let noise_height = noise_val.max(0.0) as f32 * MAX_TERRAIN_HEIGHT;
-                let bump_height = (bump_val * 2.5) as f32;
-
-                // Make "halfpipe"
-                let px =  ((x as f32 / self.cell_w as f32) - 0.5) * 2.0;
-                let halfpipe = px.powf(12.0) * 50.0;
-
-                let height = noise_height + halfpipe;
-
-                let bump_ratio = (1.0 - (height / (MAX_TERRAIN_HEIGHT * 0.2))).max(0.0);
-
-                // Increase slope along sheet z.
-                // - Starts flat, goes bumpy, ends flat.
-                // Curve: 1 - ((x / 0.4) - 1.25) ^ 4
-                let z_percent = y as f32 / self.cell_h as f32;
-                let curve = 1.0 - ((z_percent / 0.48) - 1.0).powf(4.0);
-                let slope = curve.max(0.0); // Clip floor
-
-                self.map[y][x] = ((height * slope) + (bump_height * bump_ratio)).max(0.0);
+                let h = generator.height(&noise, x, y, self.cell_w, self.cell_h);
+                self.map.push(h.max(0.0));
             }
         }
+    }
 
+    /// Forget the rows before `first_row`, once nothing needs them.
+    /// Cells keep their SHEET coordinates.
+    pub fn drop_rows(&mut self, first_row: usize) {
+        let first_row = first_row.min(self.cell_h);
+        if first_row <= self.first_row {
+            return;
+        }
+        self.map.drain(..(first_row - self.first_row) * self.cell_w);
+        self.first_row = first_row;
     }
 
     /// Given a SHEET x and y coordinate,
@@ -85,7 +186,7 @@ impl HeightMap {
 
         // Check if cell position is out of map bounds
         if cell_x >= self.cell_w || cell_y >= self.cell_h ||
-        x < 0.0 || y < 0.0 {
+        cell_y < self.first_row || x < 0.0 || y < 0.0 {
             //info!(cell_x, self.cell_w, cell_y, self.cell_h);
             None // out of bound
         } else {
@@ -95,24 +196,89 @@ impl HeightMap {
     pub fn pos_to_height(&self, x:f32, y:f32) -> Option<f32> {
         let cell_pos = self.get_cell_from_pos(x, y);
         match cell_pos {
-            Some((x, y)) => Some(self.map[y][x]),
+            Some((x, y)) => Some(self.get(x, y)),
             _ => None
         }
     }
 
+    /// Random number generator seeded from the sheet seed. Use a different
+    /// `salt` for each thing being placed so they don't share a sequence.
+    pub fn rng(&self, salt: u64) -> StdRng {
+        StdRng::seed_from_u64(((self.seed as u64) << 32) ^ salt)
+    }
+
+    /// Height at a SHEET x and y coordinate, blended
+    /// between the four surrounding cells.
+    pub fn sample_height(&self, x: f32, y: f32) -> Option<f32> {
+        let (cx, cy) = self.get_cell_from_pos(x, y)?;
+        let tx = x / self.rat_w - cx as f32;
+        let ty = y / self.rat_h - cy as f32;
+        let nx = (cx + 1).min(self.cell_w - 1);
+        let ny = (cy + 1).min(self.cell_h - 1);
+
+        let near = self.get(cx, cy) + (self.get(nx, cy) - self.get(cx, cy)) * tx;
+        let far = self.get(cx, ny) + (self.get(nx, ny) - self.get(cx, ny)) * tx;
+        Some(near + (far - near) * ty)
+    }
+
+    /// Rate of change of height along SHEET x and y.
+    pub fn gradient(&self, x: f32, y: f32) -> Option<Vec2> {
+        self.get_cell_from_pos(x, y)?;
+
+        // Sample either side, staying on the map at the edges
+        let max_x = self.w - 0.001;
+        let max_y = self.h - 0.001;
+        let (x0, x1) = ((x - self.rat_w).max(0.0), (x + self.rat_w).min(max_x));
+        let min_y = self.first_row as f32 * self.rat_h;
+        let (y0, y1) = ((y - self.rat_h).max(min_y), (y + self.rat_h).min(max_y));
+        let dx = self.sample_height(x1, y)? - self.sample_height(x0, y)?;
+        let dy = self.sample_height(x, y1)? - self.sample_height(x, y0)?;
+        Some(Vec2::new(dx / (x1 - x0), dy / (y1 - y0)))
+    }
+
+    /// Up-facing surface normal at a SHEET x and y coordinate.
+    pub fn sample_normal(&self, x: f32, y: f32) -> Option<Vec3> {
+        let g = self.gradient(x, y)?;
+        Some(Vec3::new(-g.x, 1.0, -g.y).normalize())
+    }
+
+    /// Up-facing normal at SHEET cell x/y, from the cells either side of it.
+    pub fn cell_normal(&self, x: usize, y: usize) -> Vec3 {
+        let (x0, x1) = (x.saturating_sub(1), (x + 1).min(self.cell_w - 1));
+        let (y0, y1) = (y.saturating_sub(1).max(self.first_row), (y + 1).min(self.cell_h - 1));
+        let dx = (self.get(x1, y) - self.get(x0, y)) / ((x1 - x0).max(1) as f32 * self.rat_w);
+        let dy = (self.get(x, y1) - self.get(x, y0)) / ((y1 - y0).max(1) as f32 * self.rat_h);
+        Vec3::new(-dx, 1.0, -dy).normalize()
+    }
+
+    /// How much the ground curves at SHEET cell x/y (per metre).
+    /// Positive in hollows, negative on ridges.
+    pub fn cell_curvature(&self, x: usize, y: usize) -> f32 {
+        let (x0, x1) = (x.saturating_sub(1), (x + 1).min(self.cell_w - 1));
+        let (y0, y1) = (y.saturating_sub(1).max(self.first_row), (y + 1).min(self.cell_h - 1));
+        let h = self.get(x, y);
+        let ddx = (self.get(x0, y) + self.get(x1, y) - 2.0 * h) / (self.rat_w * self.rat_w);
+        let ddy = (self.get(x, y0) + self.get(x, y1) - 2.0 * h) / (self.rat_h * self.rat_h);
+        ddx + ddy
+    }
+
+    /// Steepness (rise over run) at a SHEET x and y coordinate.
+    pub fn slope_at(&self, x: f32, y: f32) -> Option<f32> {
+        self.gradient(x, y).map(|g| g.length())
+    }
+
     // Return a random cell x/y from the height map
-    pub fn get_random_cell(&self) -> (usize, usize) {
-        let mut rng = rand::rng();
+    pub fn get_random_cell(&self, rng: &mut impl Rng) -> (usize, usize) {
         let cell_x = rng.random_range(0..self.cell_w);
-        let cell_y = rng.random_range(0..self.cell_h);
+        let cell_y = rng.random_range(self.first_row..self.cell_h);
         (cell_x, cell_y)
     }
 
-    pub fn get_random_pos_between_height(&self, min_h: f32, max_h: f32) -> (f32, f32) {
+    pub fn get_random_pos_between_height(&self, rng: &mut impl Rng, min_h: f32, max_h: f32) -> (f32, f32) {
         let mut i = 0;
         loop {
-            let cell = self.get_random_cell();
-            let h = self.map[cell.1][cell.0];
+            let cell = self.get_random_cell(rng);
+            let h = self.get(cell.0, cell.1);
             if h >= min_h && h <= max_h {
                 return (cell.0 as f32 * self.rat_w, cell.1 as f32 * self.rat_h)
             }
@@ -123,18 +289,67 @@ impl HeightMap {
         }
     }
 
+    /// Add `value` to the height at SHEET cell x/y, clamped so it never
+    /// goes below zero. When conserving soil, lowering puts the removed
+    /// volume in the budget and a raise is refused if it can't be paid for.
+    /// Changes a protected zone doesn't allow are refused too.
+    /// Returns how much the height actually changed.
+    pub fn add_height(&mut self, hm_x: usize, hm_y: usize, value: f32) -> f32 {
+        if hm_x >= self.cell_w ||
+            hm_y >= self.cell_h ||
+            hm_y < self.first_row {
+                return 0.0;
+            }
 
-    pub fn add_height(&mut self, hm_x: usize, hm_y: usize, value: f32, chunk_idx: usize) {
+        let i = (hm_y - self.first_row) * self.cell_w + hm_x;
+        let cur = self.map[i];
+        let next = (cur + value).max(0.0);
+        let change = next - cur;
+        if !self.allows(hm_x, hm_y, change) {
+            return 0.0;
+        }
+        if let Some(soil) = self.soil.as_mut() {
+            let volume = change * self.rat_w * self.rat_h;
+            if volume > *soil {
+                return 0.0;
+            }
+            *soil -= volume;
+        }
+        self.map[i] = next;
+        if change != 0.0 {
+            self.mark_dirty(DirtyRect::cell(hm_x, hm_y));
+        }
+        change
+    }
+
+    /// Whether the zones let SHEET cell x/y change by `change`
+    pub fn allows(&self, hm_x: usize, hm_y: usize, change: f32) -> bool {
+        let p = Vec2::new(hm_x as f32 * self.rat_w, hm_y as f32 * self.rat_h);
+        self.zones.iter().all(|z| !z.contains(p) || z.allows(change))
+    }
+
+    /// Whether SHEET cell x/y is in a zone that can't be edited at all
+    pub fn locked(&self, hm_x: usize, hm_y: usize) -> bool {
+        let p = Vec2::new(hm_x as f32 * self.rat_w, hm_y as f32 * self.rat_h);
+        self.zones.iter().any(|z| z.rule == ZoneRule::Locked && z.contains(p))
+    }
+
+    /// Set the height at SHEET cell x/y, ignoring the soil budget (but
+    /// keeping it up to date) and zones. Used to restore old heights.
+    pub fn set_height(&mut self, hm_x: usize, hm_y: usize, value: f32) {
         if hm_x >= self.cell_w ||
-            hm_y >= self.cell_h {
+            hm_y >= self.cell_h ||
+            hm_y < self.first_row {
                 return;
             }
 
-        //let map = &mut self.map;
-        let zoff = hm_y + (chunk_idx * CELL_SIZE);
-        let cur = (*self.map)[zoff][hm_x];
-        let next = (cur + value).max(0.0);
-        (*self.map)[zoff][hm_x] = next;
+        let i = (hm_y - self.first_row) * self.cell_w + hm_x;
+        let change = value - self.map[i];
+        if let Some(soil) = self.soil.as_mut() {
+            *soil -= change * self.rat_w * self.rat_h;
+        }
+        self.map[i] = value;
+        self.mark_dirty(DirtyRect::cell(hm_x, hm_y));
     }
 
 }
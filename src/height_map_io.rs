@@ -0,0 +1,200 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::constants::MAX_TERRAIN_HEIGHT;
+use crate::height_map::HeightMap;
+
+// Raw file layout (all little-endian):
+//   "DNKH", cell_w: u32, cell_h: u32, w: f32, h: f32,
+//   max_terrain_height: f32, seed: u32, then cell_w * cell_h f32 heights row by row.
+const RAW_MAGIC: &[u8; 4] = b"DNKH";
+
+// PNG tEXt keys for the world scale of the map
+const KEY_WIDTH: &str = "dunkling:width";
+const KEY_LENGTH: &str = "dunkling:length";
+const KEY_HEIGHT_RANGE: &str = "dunkling:height_range";
+const KEY_MAX_TERRAIN_HEIGHT: &str = "dunkling:max_terrain_height";
+const KEY_SEED: &str = "dunkling:seed";
+
+// Most cells a file can have across or along. Sheets are far smaller,
+// this only stops a broken file asking for gigabytes.
+const MAX_CELLS: usize = 4096;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Refuse sizes that can't make a height map
+fn check_size(cell_w: usize, cell_h: usize, w: f32, h: f32) -> io::Result<()> {
+    if cell_w == 0 || cell_h == 0 || cell_w > MAX_CELLS || cell_h > MAX_CELLS {
+        return Err(invalid("height map has too few or too many cells"));
+    }
+    if !(w.is_finite() && w > 0.0 && h.is_finite() && h > 0.0) {
+        return Err(invalid("height map has a bad world size"));
+    }
+    Ok(())
+}
+
+impl HeightMap {
+    /// Height that is stored as white in a png: the tallest
+    /// point on the sheet, but never less than MAX_TERRAIN_HEIGHT.
+    fn png_height_range(&self) -> f32 {
+        self.heights()
+            .iter()
+            .fold(MAX_TERRAIN_HEIGHT, |a, &b| a.max(b))
+    }
+
+    /// Write as a 16-bit grayscale png, with the world scale in tEXt chunks.
+    pub fn write_png16<W: Write>(&self, w: W) -> io::Result<()> {
+        let range = self.png_height_range();
+
+        let mut encoder = png::Encoder::new(w, self.cell_w as u32, self.cell_h as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Sixteen);
+        let text = [
+            (KEY_WIDTH, self.w.to_string()),
+            (KEY_LENGTH, self.h.to_string()),
+            (KEY_HEIGHT_RANGE, range.to_string()),
+            (KEY_MAX_TERRAIN_HEIGHT, MAX_TERRAIN_HEIGHT.to_string()),
+            (KEY_SEED, self.seed.to_string()),
+        ];
+        for (key, value) in text {
+            encoder.add_text_chunk(key.to_string(), value).map_err(io::Error::other)?;
+        }
+
+        let data: Vec<u8> = self.heights()
+            .iter()
+            .flat_map(|h| {
+                let v = (h / range).clamp(0.0, 1.0) * u16::MAX as f32;
+                (v.round() as u16).to_be_bytes()
+            })
+            .collect();
+
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        writer.write_image_data(&data).map_err(io::Error::other)?;
+        writer.finish().map_err(io::Error::other)
+    }
+
+    /// Read a grayscale png (8 or 16 bit). Maps saved by the game know their
+    /// scale, otherwise it is `w` x `h` in the world with white at MAX_TERRAIN_HEIGHT.
+    pub fn read_png16<R: Read>(r: R, w: f32, h: f32) -> io::Result<HeightMap> {
+        let mut decoder = png::Decoder::new(r);
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info().map_err(io::Error::other)?;
+
+        let text = &reader.info().uncompressed_latin1_text;
+        let meta = |key: &str| text
+            .iter()
+            .find(|t| t.keyword == key)
+            .and_then(|t| t.text.parse::<f32>().ok());
+        let w = meta(KEY_WIDTH).unwrap_or(w);
+        let h = meta(KEY_LENGTH).unwrap_or(h);
+        let range = meta(KEY_HEIGHT_RANGE).unwrap_or(MAX_TERRAIN_HEIGHT);
+        let seed = text
+            .iter()
+            .find(|t| t.keyword == KEY_SEED)
+            .and_then(|t| t.text.parse::<u32>().ok())
+            .unwrap_or(0);
+
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut buf).map_err(io::Error::other)?;
+        let (bytes, max) = match frame.bit_depth {
+            png::BitDepth::Sixteen => (2, u16::MAX as f32),
+            png::BitDepth::Eight => (1, u8::MAX as f32),
+            _ => return Err(invalid("unsupported png bit depth")),
+        };
+        // Only the first channel is used if it's not grayscale
+        let pixel = bytes * frame.color_type.samples();
+
+        let cell_w = frame.width as usize;
+        let cell_h = frame.height as usize;
+        check_size(cell_w, cell_h, w, h)?;
+        if !(range.is_finite() && range > 0.0) {
+            return Err(invalid("height map has a bad height range"));
+        }
+        let mut hm = HeightMap::flat(w, h, cell_w, cell_h, seed);
+        let heights = hm.heights_mut();
+        for (y, row) in buf.chunks(frame.line_size).take(cell_h).enumerate() {
+            for x in 0..cell_w {
+                let p = &row[x * pixel..];
+                let v = if bytes == 2 {
+                    u16::from_be_bytes([p[0], p[1]]) as f32
+                } else {
+                    p[0] as f32
+                };
+                heights[y * cell_w + x] = v / max * range;
+            }
+        }
+        Ok(hm)
+    }
+
+    /// Write the exact heights as little-endian f32s with a small header.
+    pub fn write_raw_f32<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(RAW_MAGIC)?;
+        w.write_all(&(self.cell_w as u32).to_le_bytes())?;
+        w.write_all(&(self.cell_h as u32).to_le_bytes())?;
+        w.write_all(&self.w.to_le_bytes())?;
+        w.write_all(&self.h.to_le_bytes())?;
+        w.write_all(&MAX_TERRAIN_HEIGHT.to_le_bytes())?;
+        w.write_all(&self.seed.to_le_bytes())?;
+        for h in self.heights() {
+            w.write_all(&h.to_le_bytes())?;
+        }
+        w.flush()
+    }
+
+    pub fn read_raw_f32<R: Read>(mut r: R) -> io::Result<HeightMap> {
+        let mut word = [0; 4];
+        r.read_exact(&mut word)?;
+        if &word != RAW_MAGIC {
+            return Err(invalid("not a dunkling height map"));
+        }
+        let mut next = || -> io::Result<[u8; 4]> {
+            r.read_exact(&mut word)?;
+            Ok(word)
+        };
+        let cell_w = u32::from_le_bytes(next()?) as usize;
+        let cell_h = u32::from_le_bytes(next()?) as usize;
+        let w = f32::from_le_bytes(next()?);
+        let h = f32::from_le_bytes(next()?);
+        let max_terrain_height = f32::from_le_bytes(next()?);
+        let seed = u32::from_le_bytes(next()?);
+        check_size(cell_w, cell_h, w, h)?;
+        // Heights are stored exactly, so it doesn't scale them. It's only
+        // checked, as a sign the header is sound.
+        if !(max_terrain_height.is_finite() && max_terrain_height > 0.0) {
+            return Err(invalid("height map has a bad max terrain height"));
+        }
+
+        let mut hm = HeightMap::flat(w, h, cell_w, cell_h, seed);
+        for h in hm.heights_mut() {
+            *h = f32::from_le_bytes(next()?);
+            if !h.is_finite() {
+                return Err(invalid("height map has a height that isn't a number"));
+            }
+        }
+        Ok(hm)
+    }
+
+    /// Save as `.png` or raw `.f32`, depending on the extension.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let file = BufWriter::new(File::create(path)?);
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("png") => self.write_png16(file),
+            _ => self.write_raw_f32(file),
+        }
+    }
+
+    /// Load a `.png` or raw `.f32` height map. `w` and `h` are
+    /// the world size to use if the file doesn't say.
+    pub fn load(path: impl AsRef<Path>, w: f32, h: f32) -> io::Result<HeightMap> {
+        let path = path.as_ref();
+        let file = BufReader::new(File::open(path)?);
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("png") => HeightMap::read_png16(file, w, h),
+            _ => HeightMap::read_raw_f32(file),
+        }
+    }
+}
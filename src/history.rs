@@ -0,0 +1,139 @@
+use bevy::prelude::*;
+use std::collections::HashMap;
+
+use crate::height_map::{DirtyRect, HeightMap};
+use crate::surface::{Surface, SurfaceMap};
+
+/// Cells changed by one sculpt stroke, with how much each one moved.
+/// Kept as changes rather than heights, so undoing a stroke doesn't
+/// wipe out craters and grooves the stone has made since.
+/// Painted cells keep the surface from before and after the stroke.
+#[derive(Default, Debug)]
+struct SculptStroke {
+    cells: HashMap<(usize, usize), f32>,
+    surfaces: HashMap<(usize, usize), (Surface, Surface)>,
+}
+
+/// Sculpt strokes that can be undone and redone.
+/// A stroke is everything sculpted between pressing and releasing the mouse.
+#[derive(Resource, Default)]
+pub struct SculptHistory {
+    undo: Vec<SculptStroke>,
+    redo: Vec<SculptStroke>,
+    current: Option<SculptStroke>,
+}
+
+impl SculptHistory {
+    pub fn in_stroke(&self) -> bool {
+        self.current.is_some()
+    }
+
+    pub fn begin_stroke(&mut self) {
+        self.current = Some(SculptStroke::default());
+    }
+
+    pub fn end_stroke(&mut self) {
+        if let Some(stroke) = self.current.take() {
+            if !stroke.cells.is_empty() || !stroke.surfaces.is_empty() {
+                self.undo.push(stroke);
+                self.redo.clear();
+            }
+        }
+    }
+
+    pub fn record(&mut self, x: usize, y: usize, before: f32, after: f32) {
+        if let Some(stroke) = self.current.as_mut() {
+            *stroke.cells.entry((x, y)).or_default() += after - before;
+        }
+    }
+
+    pub fn record_surface(&mut self, x: usize, y: usize, before: Surface, after: Surface) {
+        if let Some(stroke) = self.current.as_mut() {
+            stroke.surfaces.entry((x, y)).or_insert((before, after)).1 = after;
+        }
+    }
+
+    /// Revert the last stroke. Returns the cell rows it touched, or None
+    /// if there's nothing to undo or the soil budget can't pay for it.
+    pub fn undo(
+        &mut self,
+        height_map: &mut HeightMap,
+        surface_map: &mut SurfaceMap
+    ) -> Option<(usize, usize)> {
+        if !self.undo.last()?.affordable(height_map, true) {
+            info!("not enough soil to undo");
+            return None;
+        }
+        let stroke = self.undo.pop()?;
+        let rows = stroke.apply(height_map, surface_map, true);
+        self.redo.push(stroke);
+        rows
+    }
+
+    /// Re-apply the last undone stroke. Returns the cell rows it touched,
+    /// or None like `undo`.
+    pub fn redo(
+        &mut self,
+        height_map: &mut HeightMap,
+        surface_map: &mut SurfaceMap
+    ) -> Option<(usize, usize)> {
+        if !self.redo.last()?.affordable(height_map, false) {
+            info!("not enough soil to redo");
+            return None;
+        }
+        let stroke = self.redo.pop()?;
+        let rows = stroke.apply(height_map, surface_map, false);
+        self.undo.push(stroke);
+        rows
+    }
+}
+
+impl SculptStroke {
+    /// How much cell x/y moves when undoing or redoing, never taking it
+    /// below zero
+    fn change(height_map: &HeightMap, x: usize, y: usize, delta: f32, undo: bool) -> f32 {
+        let delta = if undo { -delta } else { delta };
+        delta.max(-height_map.get(x, y))
+    }
+
+    /// Whether the soil budget covers undoing (or redoing) the stroke.
+    /// Undoing a dig puts the soil back, so it can't be done once that
+    /// soil has been spent.
+    fn affordable(&self, height_map: &HeightMap, undo: bool) -> bool {
+        let Some(soil) = height_map.soil else { return true; };
+        let cell = height_map.cell_size();
+        let cost = self.cells.iter()
+            .filter(|((_, y), _)| *y >= height_map.first_row())
+            .map(|(&(x, y), &delta)| Self::change(height_map, x, y, delta, undo))
+            .sum::<f32>() * cell.x * cell.y;
+        // A little slack for rounding, when it's all being given back
+        cost <= soil + 0.001
+    }
+
+    fn apply(
+        &self,
+        height_map: &mut HeightMap,
+        surface_map: &mut SurfaceMap,
+        undo: bool
+    ) -> Option<(usize, usize)> {
+        for (&(x, y), &delta) in self.cells.iter() {
+            if y < height_map.first_row() {
+                continue;
+            }
+            let h = height_map.get(x, y) + Self::change(height_map, x, y, delta, undo);
+            height_map.set_height(x, y, h);
+        }
+        if let Some(soil) = height_map.soil.as_mut() {
+            *soil = soil.max(0.0);
+        }
+        for (&(x, y), &(before, after)) in self.surfaces.iter() {
+            if surface_map.set(x, y, if undo { before } else { after }) {
+                surface_map.mark_dirty(DirtyRect::cell(x, y));
+            }
+        }
+        let rows = self.cells.keys().chain(self.surfaces.keys()).map(|c| c.1);
+        let min_y = rows.clone().min()?;
+        let max_y = rows.max()?;
+        Some((min_y, max_y))
+    }
+}
@@ -0,0 +1,138 @@
+use avian3d::prelude::*;
+use bevy::prelude::*;
+
+use crate::coords::{CellIdx, WorldPos};
+use crate::constants::{
+    GROOVE_DEPTH,
+    GROOVE_MIN_SPEED,
+    IMPACT_CRATER_DEPTH,
+    IMPACT_CRATER_SIZE,
+    IMPACT_MAX_CRATER_SIZE,
+    IMPACT_MIN_SPEED,
+    STONE_RADIUS,
+};
+use crate::game::GamePhase;
+use crate::height_map::HeightMap;
+use crate::stamp::{BlendMode, Stamp, StampShape};
+use crate::stone::Stone;
+use crate::surface::SurfaceMap;
+
+/// What the stone was doing last step, to spot landings
+#[derive(Component, Default)]
+pub struct StoneContact {
+    airborne: bool,
+    last_vel: Vec3,
+    last_cell: Option<CellIdx>, // last cell grooved, so it only digs once per cell
+}
+
+impl StoneContact {
+    /// Off the ground since the last landing
+    pub fn airborne(&self) -> bool {
+        self.airborne
+    }
+}
+
+pub fn impact_plugin(app: &mut App) {
+    app.add_systems(
+        FixedUpdate,
+        stone_marks_terrain.run_if(in_state(GamePhase::Sculpting))
+    );
+}
+
+/// Crater for landing at `speed` m/s into the ground, if it's hard enough
+pub fn crater_stamp(speed: f32) -> Option<Stamp> {
+    if speed < IMPACT_MIN_SPEED {
+        return None;
+    }
+    let size = (speed * IMPACT_CRATER_SIZE).min(IMPACT_MAX_CRATER_SIZE);
+    let depth = speed * IMPACT_CRATER_DEPTH;
+    Some(Stamp::new(StampShape::Crater, Vec2::splat(size), depth).with_blend(BlendMode::Add))
+}
+
+impl HeightMap {
+    /// Leave a crater at SHEET cell x/y. The stone's marks don't touch
+    /// the sculpting soil budget, but do respect the zones.
+    pub fn impact(&mut self, cell: (usize, usize), speed: f32) {
+        let Some(stamp) = crater_stamp(speed) else { return; };
+        let soil = self.soil.take();
+        self.apply_stamp(&stamp, cell);
+        self.soil = soil;
+    }
+
+    /// Press a groove `depth` deep into SHEET cell x/y and its neighbours
+    pub fn groove(&mut self, cell: (usize, usize), depth: f32) {
+        let soil = self.soil.take();
+        let (x, y) = cell;
+        for j in y.saturating_sub(1)..=y + 1 {
+            for i in x.saturating_sub(1)..=x + 1 {
+                // Shallower at the sides
+                let side = if i == x && j == y { 1.0 } else { 0.5 };
+                self.add_height(i, j, -depth * side);
+            }
+        }
+        self.soil = soil;
+    }
+}
+
+/// Hard landings leave a crater, rolling over soft ground leaves a groove.
+/// Changes get marked dirty so the chunks sync like they do for sculpting.
+/// Any stone can, not just the one being thrown.
+fn stone_marks_terrain(
+    mut stones: Query<(&Transform, &LinearVelocity, &mut StoneContact), With<Stone>>,
+    mut height_map: ResMut<HeightMap>,
+    surface_map: Res<SurfaceMap>,
+) {
+    for (t, vel, mut contact) in stones.iter_mut() {
+        mark_terrain(t, vel, &mut contact, &mut height_map, &surface_map);
+    }
+}
+
+fn mark_terrain(
+    t: &Transform,
+    vel: &LinearVelocity,
+    contact: &mut StoneContact,
+    height_map: &mut HeightMap,
+    surface_map: &SurfaceMap,
+) {
+    let last_vel = std::mem::replace(&mut contact.last_vel, vel.0);
+
+    let p = WorldPos(t.translation).to_sheet().0;
+    let (Some(cell), Some(h), Some(normal)) = (
+        WorldPos(t.translation).to_cell(height_map),
+        height_map.sample_height(p.x, p.z),
+        height_map.sample_normal(p.x, p.z),
+    ) else {
+        contact.airborne = true;
+        return;
+    };
+
+    // Distance from the bottom of the stone to the ground, along the normal
+    let gap = (p.y - h) * normal.y - STONE_RADIUS;
+    if gap > STONE_RADIUS * 0.2 {
+        contact.airborne = true;
+        return;
+    }
+    if gap > STONE_RADIUS * 0.05 {
+        return;
+    }
+
+    if contact.airborne {
+        contact.airborne = false;
+        let speed = -last_vel.dot(normal);
+        if speed >= IMPACT_MIN_SPEED {
+            info!("impact at {:.0} m/s", speed);
+            height_map.impact(cell.into(), speed);
+            contact.last_cell = Some(cell);
+            return;
+        }
+    }
+
+    let softness = surface_map.get(cell.x, cell.y).softness();
+    if softness > 0.0
+        && vel.0.length() > GROOVE_MIN_SPEED
+        && contact.last_cell != Some(cell)
+    {
+        height_map.groove(cell.into(), GROOVE_DEPTH * softness);
+        contact.last_cell = Some(cell);
+    }
+}
@@ -1,15 +1,30 @@
 mod game;
+pub mod brush;
 pub mod camera;
 pub mod chunk;
+pub mod color_ramp;
 pub mod constants;
+pub mod coords;
+pub mod erosion;
+pub mod generator;
 pub mod height_map;
+pub mod height_map_io;
+pub mod history;
+pub mod impact;
 pub mod player;
 pub mod powerups;
+pub mod roll_line;
 pub mod sheet;
+pub mod spin;
 pub mod splash;
+pub mod stamp;
 pub mod stone;
+pub mod surface;
+pub mod sweep;
 pub mod timey;
 pub mod townsfolk;
+pub mod water;
+pub mod zone;
 
 #[cfg(test)]
 mod tests;
@@ -8,63 +8,99 @@ use bevy::{
 
 use crate::game::{
     GamePhase,
-    OnGameScreen,
+    OnThrowScreen,
     BigThor,
     HiScore
 };
-use crate::sheet::{Sheet, TerrainSculpt};
-use crate::stone::Stone;
+use crate::brush::{BrushKind, SculptBrushes};
+use crate::height_map::HeightMap;
+use crate::history::SculptHistory;
+use crate::roll_line::draw_roll_line;
+use crate::sheet::{Sheet, SculptRedo, SculptUndo, TerrainSculpt};
+use crate::spin::Spin;
+use crate::stone::{ActiveStone, Stone};
+use crate::zone::draw_zones;
 
 use crate::constants::{
+    CELL_SIZE,
     CHUNK_SIZE,
     MIN_SCULT_DIST_FROM_STONE,
     STONE_RADIUS,
+    SCULPT_STAMP_ROTATE_STEP,
+    SCULPT_STAMP_SCALE_RANGE,
+    SCULPT_STAMP_SCALE_STEP,
     SHEET_PRE_AREA,
     STONE_HURL_POWERUP_TIME, STONE_Y,
+    STONE_SPIN_STEP,
 };
 
+use std::f32::consts::TAU;
+
 const INIT_PBALL_X:f32 = STONE_RADIUS * 10.0;
 
 #[derive(Default, Debug)]
 struct Aiming {
     power_up: bool,
     power: f32,
-    angle: f32
+    angle: f32,
+    spin: f32, // kept between throws
 }
 
 #[derive(Default)]
 struct LastMouse {
     idx: usize,
     pos: Vec3,
+    sample: Option<f32>, // height where the current stroke started
 }
 
 #[derive(Component)]
 struct PowerBall;
 
+#[derive(Component)]
+struct TextBrush;
+
+#[derive(Component)]
+struct TextSoil;
+
+#[derive(Component)]
+struct TextSpin;
+
 #[derive(Debug, Event)]
 pub struct HurlStone {
     pub power: f32,
     pub angle: f32,
+    pub spin: f32, // see `Spin`
 }
 
 #[derive(Debug, Event)]
 pub struct HurlAimAndPower {
     pub power: f32,
     pub angle: f32,
+    pub spin: f32,
     pub reset: bool
 }
 
 pub fn player_plugin(app: &mut App) {
+    app.init_resource::<SculptBrushes>();
+
     app.add_systems(OnEnter(GamePhase::Aiming), setup_aim);
+    app.add_systems(OnEnter(GamePhase::Sculpting), setup_sculpt);
 
     app.add_systems(Update, (
         aim_and_powerup_for_hurl,
     ).run_if(in_state(GamePhase::Aiming)));
 
     app.add_systems(Update, (
+        select_brush,
+        adjust_stamp,
+        undo_redo_sculpt,
         click_terrain,
         cheat_control_stone,
-        draw_sheet_intersections
+        draw_sheet_intersections,
+        draw_zones,
+        draw_roll_line,
+        text_brush,
+        text_soil,
     ).run_if(in_state(GamePhase::Sculpting)));
 
     app.add_observer(do_powerup_viz);
@@ -78,7 +114,7 @@ fn setup_aim(
 ) {
     // Add powerball meter
     commands.spawn((
-        OnGameScreen,
+        OnThrowScreen,
         PowerBall,
         LinearVelocity(Vec3::new(0.0, 0.0, 160.0)),
         AngularVelocity(Vec3::new( 10.0, 0.0, 0.0)),
@@ -86,6 +122,182 @@ fn setup_aim(
         MeshMaterial3d(materials.add(Color::srgb_u8(124, 144, 255))),
         Transform::from_xyz(INIT_PBALL_X, STONE_Y + 30.0, -CHUNK_SIZE + SHEET_PRE_AREA * 2.0),
     ));
+
+    commands.spawn((
+        TextFont {
+            font_size: 18.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(43.0),
+            left: Val::Px(5.0),
+            ..default()
+        },
+        OnThrowScreen,
+    ))
+        .with_child( Text::new("Spin (Q/E):"))
+        .with_child((
+            Text::new(" none"),
+            TextSpin
+        ));
+}
+
+fn setup_sculpt(
+    mut commands: Commands,
+) {
+    commands.spawn((
+        TextFont {
+            font_size: 18.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(5.0),
+            left: Val::Px(5.0),
+            ..default()
+        },
+        OnThrowScreen,
+    ))
+        .with_child( Text::new("Brush:"))
+        .with_child((
+            Text::new(""),
+            TextBrush
+        ));
+
+    commands.spawn((
+        TextFont {
+            font_size: 18.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(24.0),
+            left: Val::Px(5.0),
+            ..default()
+        },
+        OnThrowScreen,
+    ))
+        .with_child( Text::new("Soil:"))
+        .with_child((
+            Text::new(""),
+            TextSoil
+        ));
+}
+
+/// Number keys pick the sculpt brush
+fn select_brush(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut brushes: ResMut<SculptBrushes>,
+) {
+    let digits = [
+        KeyCode::Digit1,
+        KeyCode::Digit2,
+        KeyCode::Digit3,
+        KeyCode::Digit4,
+        KeyCode::Digit5,
+        KeyCode::Digit6,
+        KeyCode::Digit7,
+        KeyCode::Digit8,
+    ];
+    for (i, key) in digits.iter().enumerate() {
+        if keys.just_pressed(*key) && i < brushes.brushes.len() {
+            // Pressing the paint or stamp brush again picks the next surface/shape
+            if brushes.active == i {
+                let brush = &mut brushes.brushes[i];
+                match brush.kind {
+                    BrushKind::Paint(surface) => brush.kind = BrushKind::Paint(surface.next()),
+                    BrushKind::Stamp(shape) => brush.kind = BrushKind::Stamp(shape.next()),
+                    _ => {},
+                }
+            }
+            brushes.active = i;
+        }
+    }
+}
+
+/// With the stamp brush: [ and ] shrink and grow it, comma and period
+/// turn it, and B picks how it blends (the shape's own, then each mode)
+fn adjust_stamp(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut brushes: ResMut<SculptBrushes>,
+) {
+    let active = brushes.active;
+    let brush = &mut brushes.brushes[active];
+    let BrushKind::Stamp(shape) = brush.kind else { return; };
+
+    let (min, max) = SCULPT_STAMP_SCALE_RANGE;
+    if keys.just_pressed(KeyCode::BracketLeft) {
+        brush.scale = (brush.scale / SCULPT_STAMP_SCALE_STEP).clamp(min, max);
+    }
+    if keys.just_pressed(KeyCode::BracketRight) {
+        brush.scale = (brush.scale * SCULPT_STAMP_SCALE_STEP).clamp(min, max);
+    }
+
+    let step = SCULPT_STAMP_ROTATE_STEP.to_radians();
+    if keys.just_pressed(KeyCode::Comma) {
+        brush.rotation = (brush.rotation - step).rem_euclid(TAU);
+    }
+    if keys.just_pressed(KeyCode::Period) {
+        brush.rotation = (brush.rotation + step).rem_euclid(TAU);
+    }
+
+    if keys.just_pressed(KeyCode::KeyB) {
+        // Back to the shape's own once every mode has had a turn
+        brush.blend = match brush.blend {
+            None => Some(shape.default_blend().next()),
+            Some(b) if b.next() == shape.default_blend() => None,
+            Some(b) => Some(b.next()),
+        };
+    }
+}
+
+/// Ctrl-Z to undo a sculpt stroke, Ctrl-Y to redo it
+fn undo_redo_sculpt(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut commands: Commands,
+) {
+    let is_ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    if !is_ctrl {
+        return;
+    }
+    if keys.just_pressed(KeyCode::KeyZ) {
+        commands.trigger(SculptUndo);
+    }
+    if keys.just_pressed(KeyCode::KeyY) {
+        commands.trigger(SculptRedo);
+    }
+}
+
+fn text_brush(
+    mut txt: Query<&mut Text, With<TextBrush>>,
+    brushes: Res<SculptBrushes>,
+) {
+    let brush = brushes.active();
+    let stamp = match brush.kind {
+        BrushKind::Stamp(shape) => format!(
+            " x{:.2} ([/]) {:.0}deg (,/.) {:?} (B)",
+            brush.scale,
+            brush.rotation.to_degrees(),
+            brush.blend.unwrap_or(shape.default_blend()),
+        ),
+        _ => String::new(),
+    };
+    for mut span in txt.iter_mut() {
+        span.0 = format!(" {:?}{} (1-{})", brush.kind, stamp, brushes.brushes.len());
+    }
+}
+
+fn text_soil(
+    mut txt: Query<&mut Text, With<TextSoil>>,
+    height_map: Res<HeightMap>,
+) {
+    for mut span in txt.iter_mut() {
+        span.0 = match height_map.soil {
+            Some(soil) => format!(" {soil:.0}"),
+            None => " unlimited".to_string(),
+        };
+    }
 }
 
 fn click_terrain(
@@ -96,6 +308,8 @@ fn click_terrain(
     mut ray_cast: MeshRayCast,
     terrain_query: Query<(Entity, &Mesh3d), With<Sheet>>,
     stone_query: Query<&Transform, With<Stone>>,
+    brushes: Res<SculptBrushes>,
+    mut history: ResMut<SculptHistory>,
     mut last_mouse: Local<LastMouse>,
     mut commands: Commands,
 ) {
@@ -103,7 +317,17 @@ fn click_terrain(
     let is_left = buttons.pressed(MouseButton::Left);
     let is_right = buttons.pressed(MouseButton::Right) || is_super;
     let is_shift = keys.pressed(KeyCode::ShiftLeft);
+
+    // A stroke (one undo step) lasts from mouse press to release
+    if !(is_left || is_right) {
+        history.end_stroke();
+    } else if !history.in_stroke() {
+        history.begin_stroke();
+    }
+
     if is_shift || !(is_left || is_right) {
+        // Stroke is over
+        last_mouse.sample = None;
         return;
     }
 
@@ -116,30 +340,40 @@ fn click_terrain(
         return;
     };
 
-    let stone_pos_opt = stone_query.get_single();
-
     let filter = |entity| terrain_query.contains(entity);
     // let early_exit_test = |_entity| false;
     let settings = RayCastSettings::default()
         .with_filter(&filter);
     let hits = ray_cast.cast_ray(ray, &settings);
     for (e, rmh) in hits.iter() {
-        // Don't sculpt if too close to stone
-        if let Ok(pos) = stone_pos_opt {
-            let dist_to_stone = pos.translation.distance(rmh.point);
-            if dist_to_stone < MIN_SCULT_DIST_FROM_STONE {
-                continue;
-            }
+        // Don't sculpt if too close to any stone
+        let near_stone = stone_query
+            .iter()
+            .any(|pos| pos.translation.distance(rmh.point) < MIN_SCULT_DIST_FROM_STONE);
+        if near_stone {
+            continue;
         }
 
         if let Some(idx) = rmh.triangle_index {
             let dist_mouse_moved = rmh.point.xz().distance(last_mouse.pos.xz());
             if dist_mouse_moved > 1.0 {
+                // New strokes default to sloping down the sheet
+                let start = last_mouse.sample.is_none();
+                let dir = if !start {
+                    (rmh.point.xz() - last_mouse.pos.xz()).normalize()
+                } else {
+                    Vec2::Y
+                };
+                let sample = *last_mouse.sample.get_or_insert(rmh.point.y);
                 commands.trigger_targets(
                     TerrainSculpt {
-                        up: is_right,
+                        brush: brushes.active(),
+                        invert: is_right,
                         idx,
                         p1: rmh.point,
+                        dir,
+                        sample,
+                        start,
                     },
                     e.clone()
                 );
@@ -154,7 +388,7 @@ fn click_terrain(
 
 fn cheat_control_stone(
     input: Res<ButtonInput<KeyCode>>,
-    mut stone: Query<&mut LinearVelocity, With<Stone>>,
+    mut stone: Query<&mut LinearVelocity, With<ActiveStone>>,
     mut hi: ResMut<HiScore>
 ){
     let Ok(mut vel_vec) = stone.get_single_mut() else { return; };
@@ -182,7 +416,12 @@ fn cheat_control_stone(
     }
 }
 
-fn draw_sheet_intersections(pointers: Query<&PointerInteraction>, mut gizmos: Gizmos) {
+fn draw_sheet_intersections(
+    pointers: Query<&PointerInteraction>,
+    brushes: Res<SculptBrushes>,
+    mut gizmos: Gizmos
+) {
+    let brush_size = brushes.active().radius as f32 * CHUNK_SIZE / CELL_SIZE as f32;
     for (point, normal) in pointers
         .iter()
         .filter_map(|interaction| interaction.get_nearest_hit())
@@ -190,6 +429,11 @@ fn draw_sheet_intersections(pointers: Query<&PointerInteraction>, mut gizmos: Gi
     {
         gizmos.sphere(point, 5.0, RED_500);
         gizmos.arrow(point, point + normal.normalize() * 5.0, PINK_100);
+        gizmos.circle(
+            Isometry3d::new(point, Quat::from_rotation_arc(Vec3::Z, normal.normalize())),
+            brush_size,
+            PINK_100
+        );
     }
 }
 
@@ -215,6 +459,14 @@ fn aim_and_powerup_for_hurl(
         .map(|v| { v / window.size() })
         .and_then(|pos| { Some(aim.angle = pos.x - 0.5) });
 
+    // Q for more out-turn, E for more in-turn
+    if keys.just_pressed(KeyCode::KeyQ) {
+        aim.spin = (aim.spin - STONE_SPIN_STEP).max(-1.0);
+    }
+    if keys.just_pressed(KeyCode::KeyE) {
+        aim.spin = (aim.spin + STONE_SPIN_STEP).min(1.0);
+    }
+
     if aim.power_up  {
         aim.power = (aim.power + time.delta_secs()).min(STONE_HURL_POWERUP_TIME);
     }
@@ -234,25 +486,30 @@ fn aim_and_powerup_for_hurl(
     commands.trigger(HurlAimAndPower {
         power: if fired { 0.0 } else { ratio },
         angle: if fired { 0.0 } else { aim.angle },
+        spin: aim.spin,
         reset: fired
     });
     if fired {
-        commands.trigger(HurlStone { power: ratio, angle: aim.angle });
+        commands.trigger(HurlStone { power: ratio, angle: aim.angle, spin: aim.spin });
     }
 
 }
 
 
-/// Move thor and powerball to show aim and power
+/// Move thor and powerball to show aim and power, and say what the spin is
 fn do_powerup_viz(
     trigger: Trigger<HurlAimAndPower>,
     mut powerball: Query<&mut Transform, With<PowerBall>>,
-    mut thor: Query<&mut Transform, (With<BigThor>, Without<PowerBall>)>
-
+    mut thor: Query<&mut Transform, (With<BigThor>, Without<PowerBall>)>,
+    mut txt: Query<&mut Text, With<TextSpin>>,
 ) {
+    let ev = trigger.event();
+    for mut span in txt.iter_mut() {
+        span.0 = format!(" {}", Spin(ev.spin).label());
+    }
+
     let Ok(mut pball) = powerball.get_single_mut() else { return; };
     let Ok(mut thor) = thor.get_single_mut() else { return; };
-    let ev = trigger.event();
     let ratio = ev.power;
 
     if ev.reset {
@@ -1,6 +1,6 @@
 use bevy::prelude::*;
 use avian3d::prelude::{Collider, CollisionLayers, CollisionStarted, LinearVelocity};
-use crate::{sheet::TerrainCreated, constants::{CHUNK_SIZE, SHEET_TOTAL}, height_map::HeightMap, game::{OnGameScreen, CollisionLayer, GameState}, stone::Stone};
+use crate::{sheet::{SheetMode, TerrainCreated}, coords::SheetPos, constants::{CHUNK_SIZE, SHEET_TOTAL, SHEET_WIDTH}, height_map::HeightMap, game::{OnGameScreen, CollisionLayer, GameState}, stone::{ActiveStone, Stone}};
 use rand::prelude::*;
 
 
@@ -25,9 +25,16 @@ pub fn spawn_powerups(
     height_map: Res<HeightMap>,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
+    mode: Res<SheetMode>,
 ) {
-    let mut rng = rand::rng();
-    let w = CHUNK_SIZE;
+    // Endless sheets go without. They're scattered over the whole sheet
+    // at once, which would only cover the first chunks of an endless one.
+    if *mode == SheetMode::Endless {
+        return;
+    }
+
+    let mut rng = height_map.rng(1);
+    let w = SHEET_WIDTH;
 
     let material_handle = materials.add(StandardMaterial {
         ..default()
@@ -37,8 +44,8 @@ pub fn spawn_powerups(
 
         let x = rng.random_range(0.0..w); // right(0) to left (w)
         let z = rng.random_range(0.0..SHEET_TOTAL - CHUNK_SIZE * 2.0);
-        let y = height_map.pos_to_height(x, z).unwrap_or(0.0) + rng.random_range(-20.0..35.0);
-        let pos = Vec3::new(x - w / 2.0, y, z - CHUNK_SIZE / 2.0);
+        let y = height_map.sample_height(x, z).unwrap_or(0.0) + rng.random_range(-20.0..35.0);
+        let pos = SheetPos::new(x, y, z).to_world().0;
 
         let size = rng.random_range(8.0..22.0);
 
@@ -67,7 +74,7 @@ pub fn spawn_powerups(
 
 fn detect_collisions(
     mut collision_event_reader: EventReader<CollisionStarted>,
-    stone: Query<Entity, With<Stone>>,
+    stone: Query<Entity, With<ActiveStone>>,
     powerups: Query<(Entity, &PowerupSensor), Without<Stone>>,
     mut commands: Commands
 ) {
@@ -95,7 +102,7 @@ fn detect_collisions(
 
 fn on_powerup_hit(
     trigger: Trigger<PowerupHit>,
-    mut stone: Query<&mut LinearVelocity, With<Stone>>,
+    mut stone: Query<&mut LinearVelocity, With<ActiveStone>>,
 ) {
     let Ok(mut vel_vec) = stone.get_single_mut() else { return; };
     let acc = trigger.event().speed;
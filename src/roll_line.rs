@@ -0,0 +1,82 @@
+use avian3d::prelude::*;
+use bevy::{
+    color::palettes::css::YELLOW,
+    prelude::*,
+};
+
+use crate::constants::{STONE_DAMPENING, STONE_STOP_VEL};
+use crate::coords::{SheetPos, WorldPos};
+use crate::height_map::HeightMap;
+use crate::spin::Spin;
+use crate::stone::ActiveStone;
+
+/// How the predicted stone rolls
+#[derive(Clone, Debug)]
+pub struct RollSettings {
+    pub step: f32, // seconds between points
+    pub max_steps: usize,
+    pub gravity: f32,
+    pub damping: f32, // same as the stone's LinearDamping
+    pub stop_speed: f32,
+    pub spin: Spin, // bends the path like it does the stone's
+}
+
+impl Default for RollSettings {
+    fn default() -> Self {
+        Self {
+            step: 0.05,
+            max_steps: 600,
+            gravity: 9.81,
+            damping: STONE_DAMPENING,
+            stop_speed: STONE_STOP_VEL,
+            spin: Spin::default(),
+        }
+    }
+}
+
+impl HeightMap {
+    /// Where a ball rolling from SHEET position `from` at `vel` (m/s,
+    /// across and along the sheet) goes. It stays on the ground and only
+    /// feels the slope, damping and curl: no bumps, surfaces or water.
+    /// Points are on the ground, one per step, until it stops or leaves
+    /// the sheet. Standing still it runs down the steepest slope.
+    pub fn predict_roll(&self, from: SheetPos, vel: Vec2, settings: &RollSettings) -> Vec<SheetPos> {
+        let mut p = from.xz();
+        let mut v = vel;
+        let mut path = vec![];
+        for _ in 0..settings.max_steps {
+            let (Some(h), Some(g)) = (self.sample_height(p.x, p.y), self.gradient(p.x, p.y)) else {
+                break;
+            };
+            path.push(SheetPos::new(p.x, h, p.y));
+
+            // Across the ground part of gravity down the slope (5/7 of
+            // it for a solid ball that rolls rather than slides), and the curl
+            let accel = -g * settings.gravity * (5.0 / 7.0) / (1.0 + g.length_squared())
+                + settings.spin.curl(Vec3::new(v.x, 0.0, v.y)).xz();
+            v = (v + accel * settings.step) / (1.0 + settings.damping * settings.step);
+            // Nothing left to roll it: flat, or settled in a dip
+            if v.length() < settings.stop_speed && accel.length() < settings.stop_speed {
+                break;
+            }
+            p += v * settings.step;
+        }
+        path
+    }
+}
+
+/// Show where the stone would roll from here on the terrain as it
+/// is now, so sculpting isn't guesswork
+pub fn draw_roll_line(
+    stone: Query<(&Transform, &LinearVelocity, &Spin), With<ActiveStone>>,
+    height_map: Res<HeightMap>,
+    mut gizmos: Gizmos,
+) {
+    let Ok((t, vel, spin)) = stone.get_single() else { return; };
+    let path = height_map.predict_roll(
+        WorldPos(t.translation).to_sheet(),
+        vel.0.xz(),
+        &RollSettings { spin: *spin, ..default() }
+    );
+    gizmos.linestrip(path.iter().map(|p| p.to_world().0 + Vec3::Y), YELLOW);
+}
@@ -8,25 +8,85 @@ use bevy::{
 use crate::{constants::{
     CELL_SIZE,
     CHUNK_SIZE,
+    ENDLESS_CHUNKS_AHEAD,
+    ENDLESS_CHUNKS_BEHIND,
     SHEET_TOTAL,
+    SHEET_WIDTH,
     NUM_CHUNKS,
-    SCULPT_RAISE_POWER,
-    SCULPT_LOWER_POWER, TARGET_CENTRE
-}, stone::Stone, game::CollisionLayer};
-use crate::chunk::{SpawnChunk, sync_chunk_with_heightmap};
+    NUM_CHUNK_COLS,
+    SCULPT_COLLIDER_DELAY,
+    SCULPT_SOIL_START,
+    TARGET_CENTRE,
+    STONE_RADIUS,
+}, stone::{ActiveStone, Stone}, game::CollisionLayer};
+use crate::brush::{BrushKind, SculptBrush};
+use crate::color_ramp::TerrainTheme;
+use crate::coords::{CellIdx, WorldPos};
+use crate::chunk::{
+    SpawnChunk,
+    chunk_at,
+    chunk_collider,
+    chunks_with_cols,
+    chunks_with_rows,
+    sync_chunk_rect,
+};
+use crate::erosion::ErosionSettings;
+use crate::generator::{Endless, SheetGenerators};
 use crate::game::{GameState, OnGameScreen};
-use crate::height_map::HeightMap;
+use crate::height_map::{DirtyRect, HeightMap};
+use crate::history::SculptHistory;
+use crate::surface::{Surface, SurfaceMap};
+use crate::timey::Timey;
+use crate::water::{WaterMap, WaterSettings};
+use crate::zone::default_zones;
+use rand::prelude::*;
 
 #[derive(Component)]
 pub struct Sheet;
 
+/// Seed used to generate the sheet. If `None` a new one is picked every round.
+#[derive(Resource)]
+pub struct SheetSeed(pub Option<u32>);
+
+/// Soil each sheet starts with, so raising must be paid for by lowering.
+/// `None` sculpts freely.
+#[derive(Resource)]
+pub struct SoilBudget(pub Option<f32>);
+
+/// A height map loaded from a file, used instead of generating the sheet.
+#[derive(Resource)]
+pub struct LoadedHeightMap(pub HeightMap);
+
+/// A fixed length sheet with the hole at the end, or one that's
+/// streamed in ahead of the stone forever. Picked on the splash screen.
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SheetMode {
+    #[default]
+    Classic,
+    Endless,
+}
+
+/// What made the current sheet: a generator name, "loaded" or "endless"
+#[derive(Resource)]
+pub struct SheetName(pub String);
+
 #[derive(Debug, Event)]
 pub struct TerrainSculpt {
-    pub up: bool,
+    pub brush: SculptBrush,
+    pub invert: bool,
     pub idx: usize,
     pub p1: Vec3,
+    pub dir: Vec2, // stroke direction
+    pub sample: f32, // height at the start of the stroke
+    pub start: bool, // first sculpt of the stroke
 }
 
+#[derive(Debug, Event)]
+pub struct SculptUndo;
+
+#[derive(Debug, Event)]
+pub struct SculptRedo;
+
 #[derive(Debug, Event)]
 pub struct TerrainCreated;
 
@@ -36,15 +96,62 @@ pub struct StoneInHole;
 #[derive(Component)]
 struct HoleSensor;
 
+/// Chunk's collider is out of date. It's rebuilt when the Timey finishes.
+/// Sculpting restarts it, so a whole stroke only rebuilds it once. Other
+/// changes (like a groove carved under a rolling stone) don't, so the
+/// collider still catches up with terrain that keeps changing.
+#[derive(Component)]
+struct RebuildCollider;
+
 pub fn sheet_plugin(app: &mut App) {
     app.add_plugins(WireframePlugin);
     app.insert_resource(WireframeConfig {
         global: false,
         default_color: Color::linear_rgb(0.1,0.1, 0.),
     });
+    // Replay a sheet with eg. `SEED=1234 cargo run`
+    app.insert_resource(SheetSeed(
+        std::env::var("SEED").ok().and_then(|s| s.parse().ok())
+    ));
+    // Pay for raising by lowering with `SOIL=1 cargo run`
+    app.insert_resource(SoilBudget(
+        std::env::var("SOIL").is_ok_and(|s| s != "0").then_some(SCULPT_SOIL_START)
+    ));
+    // Play a saved or hand-painted sheet with eg. `HEIGHTMAP=sheet.png cargo run`
+    if let Ok(path) = std::env::var("HEIGHTMAP") {
+        match HeightMap::load(&path, SHEET_WIDTH, CHUNK_SIZE * NUM_CHUNKS as f32) {
+            Ok(height_map) => {
+                app.insert_resource(LoadedHeightMap(height_map));
+            },
+            Err(e) => warn!("couldn't load height map {}: {}", path, e),
+        }
+    }
+    // Pick a generator with eg. `SHEET=canyon` or `SHEET=moguls:spacing=20` (otherwise it's classic),
+    // or have them take turns with `SHEET=rotate`
+    app.insert_resource(match std::env::var("SHEET") {
+        Ok(spec) => SheetGenerators::from_spec(&spec),
+        Err(_) => SheetGenerators::default(),
+    });
+    // Skip eroding generated sheets with `EROSION=0 cargo run`
+    app.insert_resource(ErosionSettings {
+        enabled: std::env::var("EROSION").map_or(true, |s| s != "0"),
+        ..default()
+    });
+    app.init_resource::<SheetMode>();
     app.add_systems(OnEnter(GameState::InGame), setup);
-    app.add_systems(Update, detect_collisions);
+    app.add_systems(Update, (
+        detect_collisions,
+        rebuild_colliders.run_if(in_state(GameState::InGame)),
+        save_sheet.run_if(in_state(GameState::InGame)),
+        stream_chunks
+            .run_if(in_state(GameState::InGame))
+            .run_if(resource_equals(SheetMode::Endless)),
+    ));
+    // After the sculpt observers have run, so strokes show up the same frame
+    app.add_systems(PostUpdate, sync_dirty_chunks.run_if(in_state(GameState::InGame)));
     app.add_observer(terrain_sculpt);
+    app.add_observer(on_sculpt_undo);
+    app.add_observer(on_sculpt_redo);
 }
 
 fn setup(
@@ -52,6 +159,13 @@ fn setup(
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     asset_server: Res<AssetServer>,
+    sheet_seed: Res<SheetSeed>,
+    soil: Res<SoilBudget>,
+    loaded: Option<Res<LoadedHeightMap>>,
+    erosion: Res<ErosionSettings>,
+    water_settings: Res<WaterSettings>,
+    mut generators: ResMut<SheetGenerators>,
+    mode: Res<SheetMode>,
 ) {
     // Add the initial slanty chunk mesh
     /*
@@ -103,19 +217,98 @@ fn setup(
     */
 
     // Create the height map then spawn the chunk meshes
-    let height_map = HeightMap::new(
-        CHUNK_SIZE,
-        CHUNK_SIZE * NUM_CHUNKS as f32,
-        CELL_SIZE,
-        CELL_SIZE * NUM_CHUNKS as usize);
+    let seed = sheet_seed.0.unwrap_or_else(|| rand::rng().random());
+    info!("sheet seed: {}", seed);
+    let cell_w = CELL_SIZE * NUM_CHUNK_COLS as usize;
+    let cell_h = CELL_SIZE * NUM_CHUNKS as usize;
+    let generator = generators.current();
+    let generate = || {
+        info!("sheet generator: {}", generator.name());
+        let mut hm = HeightMap::generate(
+            SHEET_WIDTH,
+            CHUNK_SIZE * NUM_CHUNKS as f32,
+            cell_w,
+            cell_h,
+            seed,
+            generator
+        );
+        if generator.erodes() {
+            hm.erode(&erosion);
+        }
+        hm
+    };
+    let endless = *mode == SheetMode::Endless;
+    let (mut height_map, name) = match loaded {
+        _ if endless => {
+            // Enough for the first chunks, the rest is streamed in ahead of the stone
+            let chunks = ENDLESS_CHUNKS_AHEAD + 1;
+            let hm = HeightMap::generate(
+                SHEET_WIDTH,
+                CHUNK_SIZE * chunks as f32,
+                cell_w,
+                CELL_SIZE * chunks as usize,
+                seed,
+                &Endless::default()
+            );
+            (hm, "endless")
+        },
+        // Its cells have to line up with the chunk meshes' vertices
+        Some(loaded) if (loaded.0.cell_w, loaded.0.cell_h) == (cell_w, cell_h)
+            && (loaded.0.w, loaded.0.h) == (SHEET_WIDTH, SHEET_TOTAL) => {
+            (loaded.0.clone(), "loaded")
+        },
+        Some(loaded) => {
+            warn!("loaded height map is {}x{} cells over {}x{}m, sheet needs {}x{} over {}x{}m. \
+                Generating one instead.",
+                loaded.0.cell_w, loaded.0.cell_h, loaded.0.w, loaded.0.h,
+                cell_w, cell_h, SHEET_WIDTH, SHEET_TOTAL);
+            (generate(), generator.name())
+        },
+        None => (generate(), generator.name()),
+    };
+    commands.insert_resource(SheetName(name.to_string()));
+    if generators.rotate && !endless {
+        generators.next();
+    }
+    height_map.zones = default_zones(*mode);
+    // Rivers are dug before there's a soil budget, and are part of the sheet.
+    // A loaded sheet is left as it was made, it only gets the sea and lakes.
+    let mut water_settings = water_settings.clone();
+    if name == "loaded" {
+        water_settings.rivers = 0;
+    }
+    let water_map = WaterMap::generate(&mut height_map, &water_settings);
+    height_map.take_dirty();
+    height_map.soil = soil.0;
 
+    commands.insert_resource(SurfaceMap::generate(&height_map));
+    commands.insert_resource(water_map);
     commands.insert_resource(height_map);
+    commands.insert_resource(SculptHistory::default());
     commands.trigger(TerrainCreated);
 
-    for i in 0..NUM_CHUNKS -1{
-        commands.queue(SpawnChunk {
-            pos: IVec2::new(0, i)
-        });
+    let chunks = if endless { ENDLESS_CHUNKS_AHEAD } else { NUM_CHUNKS - 1 };
+    for i in 0..chunks {
+        for col in 0..NUM_CHUNK_COLS {
+            commands.queue(SpawnChunk {
+                pos: IVec2::new(col, i)
+            });
+        }
+    }
+
+    commands
+        .spawn((
+            Name::new("Mountain"),
+            OnGameScreen,
+            SceneRoot(
+                asset_server
+                    .load(GltfAssetLabel::Scene(0).from_asset("models/mountain.glb"))),
+            Transform::from_xyz(0.0, 0.0, -400.0).with_scale(Vec3::splat(200.0))
+        ));
+
+    // Endless sheets never end
+    if endless {
+        return;
     }
 
     // Endzone hole
@@ -168,24 +361,33 @@ fn setup(
 
     ));
 
-    commands
-        .spawn((
-            Name::new("Mountain"),
-            OnGameScreen,
-            SceneRoot(
-                asset_server
-                    .load(GltfAssetLabel::Scene(0).from_asset("models/mountain.glb"))),
-            Transform::from_xyz(0.0, 0.0, -400.0).with_scale(Vec3::splat(200.0))
-        ));
-
-
-
+}
 
+/// Press P to save the current sheet as a png and raw heights
+fn save_sheet(
+    keys: Res<ButtonInput<KeyCode>>,
+    height_map: Res<HeightMap>,
+    mode: Res<SheetMode>,
+) {
+    if !keys.just_pressed(KeyCode::KeyP) {
+        return;
+    }
+    if *mode == SheetMode::Endless {
+        warn!("can't save an endless sheet");
+        return;
+    }
+    for ext in ["png", "f32"] {
+        let path = format!("sheet-{}.{}", height_map.seed, ext);
+        match height_map.save(&path) {
+            Ok(()) => info!("saved sheet to {}", path),
+            Err(e) => warn!("couldn't save sheet to {}: {}", path, e),
+        }
+    }
 }
 
 fn detect_collisions(
     mut collision_event_reader: EventReader<CollisionStarted>,
-    stone: Query<Entity, With<Stone>>,
+    stone: Query<Entity, With<ActiveStone>>,
     hole: Query<Entity, (With<HoleSensor>, Without<Stone>)>,
     mut commands: Commands
 ) {
@@ -211,41 +413,199 @@ fn detect_collisions(
 
 pub fn terrain_sculpt(
     trigger: Trigger<TerrainSculpt>,
-    mesh_query: Query<(Entity, &Mesh3d, &Transform), With<Sheet>>,
-    mut meshes: ResMut<Assets<Mesh>>,
     mut height_map: ResMut<HeightMap>,
-    mut commands: Commands,
+    mut surface_map: ResMut<SurfaceMap>,
+    mut history: ResMut<SculptHistory>,
 ) {
-    let Ok((e, mesh_handle, t)) = mesh_query.get(trigger.entity()) else {
-        return;
-    };
-    let mut mesh = meshes.get_mut(mesh_handle).unwrap();
-
     let ev = trigger.event();
-    let up = ev.up;
     let _vert = ev.idx;
     let point = ev.p1;
 
-    let chunk_idx = (t.translation.z / CHUNK_SIZE).floor() as usize;
-
     // Get sheet position from world position
-    let p1 = point - t.translation + Vec3::new(CHUNK_SIZE * 0.5, 0.0, CHUNK_SIZE * 0.5);
-    let Some((c1x, c1y)) = height_map.get_cell_from_pos(p1.x, p1.z) else { return; };
+    let Some(CellIdx { x: c1x, y: c1y }) = WorldPos(point).to_cell(&height_map) else { return; };
 
-    let h = if up { SCULPT_RAISE_POWER } else { -SCULPT_LOWER_POWER };
+    // Nothing at all happens in a no-edit zone, not even painting
+    if height_map.locked(c1x, c1y) {
+        return;
+    }
 
-    // change the heights of surrounding verts
-    let amount = 0.8;
-    for n in get_neighbours_radius(c1x, c1y, 4, 4) {
-        let dist = 1.0 - (1.0 - n.2).powi(3);// n.2 * n.2; // 0 - 1
-        height_map.add_height(n.0, n.1, h * amount * dist, chunk_idx);
+    // Painting changes the surface, not the heights. Inverted paints grass.
+    if let BrushKind::Paint(surface) = ev.brush.kind {
+        let surface = if ev.invert { Surface::Grass } else { surface };
+        let r = ev.brush.radius;
+        let changed = surface_map.paint(c1x, c1y, r, surface);
+        for &(x, y, before) in changed.iter() {
+            history.record_surface(x, y, before, surface);
+        }
+        if !changed.is_empty() {
+            // Only the colours change, the colliders can stay
+            surface_map.mark_dirty(DirtyRect::cell(c1x, c1y).grow(r));
+        }
+        return;
     }
-    sync_chunk_with_heightmap(&mut mesh, &height_map, 0, (chunk_idx * CELL_SIZE) as i32);
 
-    // Re-add collider to match new terrain
-    commands.entity(e).remove::<Collider>();
-    commands.entity(e).insert(ColliderConstructor::TrimeshFromMeshWithConfig(TrimeshFlags::FIX_INTERNAL_EDGES));
+    // Stamps go down once per click
+    if matches!(ev.brush.kind, BrushKind::Stamp(_)) && !ev.start {
+        return;
+    }
 
+    // change the heights of surrounding verts. Dig before building
+    // so conserved soil is available to build with.
+    let mut deltas = ev.brush.deltas(&height_map, (c1x, c1y), ev.dir, ev.sample, ev.invert);
+    deltas.sort_by(|a, b| a.2.total_cmp(&b.2));
+    for (x, y, d) in deltas {
+        if x >= height_map.cell_w || y >= height_map.cell_h {
+            continue;
+        }
+        let before = height_map.get(x, y);
+        if height_map.add_height(x, y, d) != 0.0 {
+            history.record(x, y, before, height_map.get(x, y));
+        }
+    }
+}
+
+fn on_sculpt_undo(
+    _trigger: Trigger<SculptUndo>,
+    mut height_map: ResMut<HeightMap>,
+    mut surface_map: ResMut<SurfaceMap>,
+    mut history: ResMut<SculptHistory>,
+) {
+    history.undo(&mut height_map, &mut surface_map);
+}
+
+fn on_sculpt_redo(
+    _trigger: Trigger<SculptRedo>,
+    mut height_map: ResMut<HeightMap>,
+    mut surface_map: ResMut<SurfaceMap>,
+    mut history: ResMut<SculptHistory>,
+) {
+    history.redo(&mut height_map, &mut surface_map);
+}
+
+/// Update the meshes of chunks with changed cells (brushes can reach
+/// over chunk edges) and schedule their colliders to be rebuilt.
+/// Cells that were only painted or recoloured keep their colliders.
+fn sync_dirty_chunks(
+    mesh_query: Query<(Entity, &Mesh3d, &Transform, Has<RebuildCollider>), With<Sheet>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut height_map: ResMut<HeightMap>,
+    mut surface_map: ResMut<SurfaceMap>,
+    theme: Res<TerrainTheme>,
+    history: Res<SculptHistory>,
+    mut commands: Commands,
+) {
+    // Taking the rects isn't a change to the heights or surfaces
+    let heights = height_map.bypass_change_detection().take_dirty();
+    let colors = surface_map.bypass_change_detection().take_dirty();
+
+    for (rect, moved) in [(heights, true), (colors, false)] {
+        let Some(rect) = rect else { continue; };
+
+        // Normals and colors next to the changed cells move too
+        let rows = chunks_with_rows(rect.grow(1));
+        let cols = chunks_with_cols(rect.grow(1));
+        for (e, mesh_handle, t, pending) in mesh_query.iter() {
+            let pos = chunk_at(t.translation);
+            if !rows.contains(&(pos.y as usize)) || !cols.contains(&(pos.x as usize)) {
+                continue;
+            }
+            let Some(mesh) = meshes.get_mut(mesh_handle) else { continue; };
+            let (xo, yo) = (pos.x * CELL_SIZE as i32, pos.y * CELL_SIZE as i32);
+            sync_chunk_rect(mesh, &height_map, &surface_map, &theme.ramp, xo, yo, rect);
+
+            // Collider catches up once sculpting pauses, or at most once
+            // per delay for the stone's marks
+            if moved && (history.in_stroke() || !pending) {
+                commands.entity(e).insert((
+                    Timey::new(SCULPT_COLLIDER_DELAY),
+                    RebuildCollider
+                ));
+            }
+        }
+    }
+}
+
+/// Endless mode: keep chunks spawned around the stone, generating the
+/// height map ahead of it and dropping what it has left behind.
+fn stream_chunks(
+    stone: Query<&Transform, With<ActiveStone>>,
+    chunks: Query<(Entity, &Transform), (With<Sheet>, Without<Stone>)>,
+    mut height_map: ResMut<HeightMap>,
+    mut surface_map: ResMut<SurfaceMap>,
+    mut water_map: ResMut<WaterMap>,
+    mut commands: Commands,
+) {
+    let Ok(stone) = stone.get_single() else { return; };
+    let stone_chunk = chunk_at(stone.translation).y.max(0);
+    // Dropped rows are gone for good, so never go back to them
+    let first = (stone_chunk - ENDLESS_CHUNKS_BEHIND)
+        .max((height_map.first_row() / CELL_SIZE) as i32);
+    let end = stone_chunk + ENDLESS_CHUNKS_AHEAD;
+
+    // Stay a chunk ahead of the meshes, so the far edge joins up
+    let rows = (end as usize + 1) * CELL_SIZE;
+    if height_map.cell_h < rows {
+        let more = rows - height_map.cell_h;
+        height_map.extend(more, &Endless::default());
+        surface_map.extend(&height_map);
+        water_map.extend(&height_map);
+    }
+
+    let mut spawned = vec![];
+    for (e, t) in chunks.iter() {
+        let pos = chunk_at(t.translation);
+        if pos.y < first {
+            commands.entity(e).despawn_recursive();
+        } else {
+            spawned.push(pos);
+        }
+    }
+    for i in first..end {
+        for col in 0..NUM_CHUNK_COLS {
+            let pos = IVec2::new(col, i);
+            if !spawned.contains(&pos) {
+                commands.queue(SpawnChunk { pos });
+            }
+        }
+    }
+
+    let first_row = first as usize * CELL_SIZE;
+    if first_row > height_map.first_row() {
+        height_map.drop_rows(first_row);
+        surface_map.drop_rows(first_row);
+        water_map.drop_rows(first_row);
+    }
+}
+
+/// Swap in a new heightfield for chunks that have finished being sculpted
+fn rebuild_colliders(
+    mut chunks: Query<(Entity, &Transform, &mut Timey), (With<RebuildCollider>, Without<Stone>)>,
+    mut stones: Query<&mut Transform, With<Stone>>,
+    height_map: Res<HeightMap>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    for (e, t, mut timer) in chunks.iter_mut() {
+        if !timer.tick(time.delta()) {
+            continue;
+        }
+        let pos = chunk_at(t.translation);
+
+        // Replacing the collider in one go means there's never a frame without one
+        let (xo, yo) = (pos.x * CELL_SIZE as i32, pos.y * CELL_SIZE as i32);
+        commands.entity(e)
+            .insert(chunk_collider(&height_map, xo, yo))
+            .remove::<(Timey, RebuildCollider)>();
+
+        // Don't leave stones stuck under terrain that was raised around them
+        for mut stone_pos in stones.iter_mut() {
+            if let Some(h) = WorldPos(stone_pos.translation).to_sheet().ground(&height_map) {
+                if stone_pos.translation.y < h + STONE_RADIUS * 0.5 {
+                    stone_pos.translation.y = h + STONE_RADIUS;
+                }
+            }
+        }
+    }
 }
 
 
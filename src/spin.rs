@@ -0,0 +1,62 @@
+use avian3d::prelude::*;
+use bevy::prelude::*;
+
+use crate::constants::{STONE_CURL, STONE_CURL_SPEED, STONE_SPIN_RATE};
+use crate::game::GamePhase;
+use crate::impact::StoneContact;
+use crate::stone::ActiveStone;
+
+/// Turn put on the stone when it's thrown, -1 to 1. Positive is an
+/// in-turn (clockwise from above), which curls right, negative is an
+/// out-turn, which curls left.
+#[derive(Component, Default, Clone, Copy, Debug, PartialEq)]
+pub struct Spin(pub f32);
+
+impl Spin {
+    /// Sideways push from the spin for a stone moving at `vel`. Like a
+    /// curling stone it curls more as it slows down.
+    pub fn curl(&self, vel: Vec3) -> Vec3 {
+        let flat = Vec3::new(vel.x, 0.0, vel.z);
+        let speed = flat.length();
+        if speed < 0.001 || self.0 == 0.0 {
+            return Vec3::ZERO;
+        }
+        // WORLD -x is right looking down the sheet
+        let right = flat.cross(Vec3::Y) / speed;
+        right * self.0 * STONE_CURL * STONE_CURL_SPEED / (speed + STONE_CURL_SPEED)
+    }
+
+    /// Angular velocity to show the turn, about the up axis
+    pub fn angular(&self) -> Vec3 {
+        Vec3::NEG_Y * self.0 * STONE_SPIN_RATE
+    }
+
+    pub fn label(&self) -> String {
+        let amount = (self.0.abs() * 100.0).round();
+        match self.0 {
+            s if s > 0.0 => format!("in-turn {amount}%"),
+            s if s < 0.0 => format!("out-turn {amount}%"),
+            _ => "none".to_string(),
+        }
+    }
+}
+
+pub fn spin_plugin(app: &mut App) {
+    app.add_systems(
+        FixedUpdate,
+        curl_stone.run_if(in_state(GamePhase::Sculpting))
+    );
+}
+
+/// Bend the stone's path while it's on the ground
+fn curl_stone(
+    mut stone: Query<(&Spin, &StoneContact, &mut LinearVelocity), With<ActiveStone>>,
+    time: Res<Time>,
+) {
+    let Ok((spin, contact, mut vel)) = stone.get_single_mut() else { return; };
+    if contact.airborne() {
+        return;
+    }
+    let curl = spin.curl(vel.0);
+    vel.0 += curl * time.delta_secs();
+}
@@ -2,6 +2,7 @@ use bevy::prelude::*;
 
 use crate::timey::Timey;
 use crate::game::{despawn_screen, GameState};
+use crate::sheet::SheetMode;
 
 #[derive(Component)]
 struct OnSplashScreen;
@@ -39,6 +40,21 @@ fn splash_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
             ));
         });
 
+    commands.spawn((
+        TextFont {
+            font_size: 18.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(20.0),
+            left: Val::Px(20.0),
+            ..default()
+        },
+        Text::new("E: endless"),
+        OnSplashScreen,
+    ));
+
     commands.spawn((
         Timey::new(15.0),
         SplashTimer,
@@ -50,15 +66,23 @@ fn splash_setup(mut commands: Commands, asset_server: Res<AssetServer>) {
 
 pub fn countdown(
     mut game_state: ResMut<NextState<GameState>>,
+    mut mode: ResMut<SheetMode>,
     buttons: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
     time: Res<Time>,
     mut timers: Query<&mut Timey, With<SplashTimer>>,
 ) {
     for mut timer in timers.iter_mut() {
         if timer.tick(time.delta()) {
+            *mode = SheetMode::Classic;
             game_state.set(GameState::InGame);
         }
         if timer.elapsed().as_secs() > 1 && buttons.just_pressed(MouseButton::Left) {
+            *mode = SheetMode::Classic;
+            game_state.set(GameState::InGame);
+        }
+        if timer.elapsed().as_secs() > 1 && keys.just_pressed(KeyCode::KeyE) {
+            *mode = SheetMode::Endless;
             game_state.set(GameState::InGame);
         }
     }
@@ -0,0 +1,181 @@
+use bevy::prelude::*;
+
+use crate::height_map::{DirtyRect, HeightMap};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StampShape {
+    Ramp, // straight slope up to the far end
+    Kicker, // curves up, steepest at the lip
+    Crater, // dug out middle with a raised rim
+    Bowl, // smooth dish
+}
+
+/// How a stamp combines with the terrain under it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendMode {
+    Add, // shape is added to the terrain
+    Max, // terrain is raised up to the shape, never lowered
+    Min, // terrain is dug down to the shape, never raised
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 3] = [
+        BlendMode::Add,
+        BlendMode::Max,
+        BlendMode::Min,
+    ];
+
+    /// The next mode, for cycling through them
+    pub fn next(&self) -> BlendMode {
+        let i = BlendMode::ALL.iter().position(|b| b == self).unwrap_or(0);
+        BlendMode::ALL[(i + 1) % BlendMode::ALL.len()]
+    }
+
+    /// Raise <-> lower
+    pub fn invert(&self) -> BlendMode {
+        match self {
+            BlendMode::Add => BlendMode::Add,
+            BlendMode::Max => BlendMode::Min,
+            BlendMode::Min => BlendMode::Max,
+        }
+    }
+}
+
+impl StampShape {
+    pub const ALL: [StampShape; 4] = [
+        StampShape::Ramp,
+        StampShape::Kicker,
+        StampShape::Crater,
+        StampShape::Bowl,
+    ];
+
+    /// The next shape, for cycling through them
+    pub fn next(&self) -> StampShape {
+        let i = StampShape::ALL.iter().position(|s| s == self).unwrap_or(0);
+        StampShape::ALL[(i + 1) % StampShape::ALL.len()]
+    }
+
+    pub fn default_blend(&self) -> BlendMode {
+        match self {
+            StampShape::Ramp | StampShape::Kicker => BlendMode::Max,
+            StampShape::Crater => BlendMode::Add,
+            StampShape::Bowl => BlendMode::Min,
+        }
+    }
+
+    /// Height from -1 to 1 at local u (across) and v (forward) in -1..1,
+    /// or None if that's outside the shape.
+    fn profile(&self, u: f32, v: f32) -> Option<f32> {
+        let r = (u * u + v * v).sqrt();
+        let in_rect = u.abs() <= 1.0 && v.abs() <= 1.0;
+        match self {
+            StampShape::Ramp if in_rect => Some((v + 1.0) * 0.5),
+            StampShape::Kicker if in_rect => Some(((v + 1.0) * 0.5).powi(2)),
+            StampShape::Crater if r <= 1.0 => {
+                if r < 0.7 {
+                    Some(-(1.0 - (r / 0.7).powi(2)))
+                } else {
+                    // Rim peaks between the hole and the edge
+                    Some((1.0 - ((r - 0.85) / 0.15).powi(2)).max(0.0) * 0.3)
+                }
+            },
+            StampShape::Bowl if r <= 1.0 => Some(-(1.0 - r * r)),
+            _ => None,
+        }
+    }
+}
+
+/// A parametric shape to blend into the height map.
+#[derive(Debug, Clone, Copy)]
+pub struct Stamp {
+    pub shape: StampShape,
+    pub blend: BlendMode,
+    pub size: Vec2, // half width and half length, in cells
+    pub height: f32, // metres at the top (or bottom) of the shape
+    pub rotation: f32, // radians, 0 faces down the sheet
+}
+
+impl Stamp {
+    pub fn new(shape: StampShape, size: Vec2, height: f32) -> Self {
+        Self {
+            shape,
+            blend: shape.default_blend(),
+            size,
+            height,
+            rotation: 0.0,
+        }
+    }
+
+    pub fn with_blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: f32) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    /// Face along `dir` (in sheet space)
+    pub fn facing(self, dir: Vec2) -> Self {
+        self.with_rotation(Vec2::Y.angle_to(dir))
+    }
+
+    /// Grow (or shrink) the shape, keeping its proportions
+    pub fn scaled(mut self, scale: f32) -> Self {
+        self.size *= scale;
+        self.height *= scale;
+        self
+    }
+
+    /// Cells the stamp can touch when centred on `cell`
+    pub fn bounds(&self, cell: (usize, usize)) -> DirtyRect {
+        let r = self.size.length().ceil() as usize;
+        DirtyRect::cell(cell.0, cell.1).grow(r)
+    }
+
+    /// Height changes for every cell under the stamp centred at `cell`.
+    /// Max and Min shapes sit on the height at the centre.
+    pub fn deltas(&self, hm: &HeightMap, cell: (usize, usize)) -> Vec<(usize, usize, f32)> {
+        if cell.0 >= hm.cell_w || cell.1 >= hm.cell_h {
+            return vec![];
+        }
+        let base = hm.get(cell.0, cell.1);
+        let rot = Vec2::from_angle(-self.rotation);
+        let rect = self.bounds(cell);
+
+        let mut deltas = vec![];
+        for y in rect.min_y..=rect.max_y.min(hm.cell_h - 1) {
+            for x in rect.min_x..=rect.max_x.min(hm.cell_w - 1) {
+                let off = Vec2::new(x as f32 - cell.0 as f32, y as f32 - cell.1 as f32);
+                let local = rot.rotate(off) / self.size;
+                let Some(p) = self.shape.profile(local.x, local.y) else { continue; };
+
+                let cur = hm.get(x, y);
+                let shape = p * self.height;
+                let d = match self.blend {
+                    BlendMode::Add => shape,
+                    BlendMode::Max => (base + shape - cur).max(0.0),
+                    BlendMode::Min => (base + shape - cur).min(0.0),
+                };
+                if d != 0.0 {
+                    deltas.push((x, y, d));
+                }
+            }
+        }
+        deltas
+    }
+}
+
+impl HeightMap {
+    /// Blend a stamp in centred at SHEET cell x/y. Goes through `add_height`,
+    /// so it's marked dirty and paid for with soil.
+    pub fn apply_stamp(&mut self, stamp: &Stamp, cell: (usize, usize)) {
+        let mut deltas = stamp.deltas(self, cell);
+        // Dig before building
+        deltas.sort_by(|a, b| a.2.total_cmp(&b.2));
+        for (x, y, d) in deltas {
+            self.add_height(x, y, d);
+        }
+    }
+}
@@ -1,10 +1,13 @@
 use bevy::prelude::*;
 use avian3d::prelude::*;
 
+use crate::coords::WorldPos;
 use crate::game::{GameState, OnGameScreen, Spotty, CollisionLayer};
+use crate::impact::StoneContact;
+use crate::spin::Spin;
 
 use crate::constants::{
-    CHUNK_SIZE,
+    SHEET_WIDTH,
     STONE_ANGULAR_DAMPENING,
     STONE_DAMPENING,
     STONE_MAX_VEL,
@@ -17,12 +20,27 @@ use crate::constants::{
 #[derive(Component)]
 pub struct Stone;
 
+/// The stone being thrown. Stones from earlier throws in the end stay
+/// on the sheet, and can be knocked about by it.
+#[derive(Component)]
+pub struct ActiveStone;
+
+/// Put a new active stone at the start for the next throw
+#[derive(Debug, Event)]
+pub struct SpawnStone;
+
 pub fn stone_plugin(app: &mut App) {
     app.add_systems(OnEnter(GameState::InGame), setup);
     app.add_systems(Update, stone_update);
+    app.add_observer(on_spawn_stone);
 }
 
-fn setup(
+fn setup(mut commands: Commands) {
+    commands.trigger(SpawnStone);
+}
+
+fn on_spawn_stone(
+    _trigger: Trigger<SpawnStone>,
     mut commands: Commands,
     mut meshes: ResMut<Assets<Mesh>>,
     mut materials: ResMut<Assets<StandardMaterial>>,
@@ -39,6 +57,9 @@ fn setup(
     // stone
     commands.spawn((
         Stone,
+        ActiveStone,
+        StoneContact::default(),
+        Spin::default(), // set when it's thrown
         OnGameScreen,
         //RigidBody::Dynamic, // Gets added when you fire
         Collider::sphere(STONE_RADIUS),
@@ -46,6 +67,7 @@ fn setup(
         CollisionLayers::new(
             [CollisionLayer::Stone],
             [
+                CollisionLayer::Stone, // knocks the other stones about
                 CollisionLayer::Terrain,
                 CollisionLayer::Sensors,
                 CollisionLayer::Townsfolk
@@ -54,7 +76,9 @@ fn setup(
         LinearDamping(STONE_DAMPENING),
         AngularDamping(STONE_ANGULAR_DAMPENING),
         MaxLinearSpeed(STONE_MAX_VEL),
-        Friction::new(1.0),
+        // Set from the surface it's on, see surface.rs
+        Friction::new(1.0).with_combine_rule(CoefficientCombine::Multiply),
+        Restitution::new(0.0).with_combine_rule(CoefficientCombine::Max),
         //CollisionMargin(0.1),
         //Mass(weight),
         LinearVelocity(Vec3::new(0.0, 0.0, 160.0)),
@@ -66,24 +90,34 @@ fn setup(
     ));
 }
 
+/// Spotlight on the active stone. Stones that fall off the sheet are
+/// gone, unless it's the active one, which goes back to the start.
 fn stone_update (
-    mut stone: Query<(&mut Transform, &mut LinearVelocity), With<Stone>>,
+    mut stones: Query<(Entity, &mut Transform, &mut LinearVelocity, Has<ActiveStone>), With<Stone>>,
     mut spotty: Query<&mut Transform, (With<Spotty>, Without<Stone>)>,
+    mut commands: Commands,
 ){
-    let Ok((mut stone_pos, mut vel_vec)) = stone.get_single_mut() else { return; };
-    let Ok(mut spot_pos) = spotty.get_single_mut() else { return; };
-
-    spot_pos.translation = stone_pos.translation + Vec3::new(1.0, STONE_RADIUS * 2.0, 1.0);
+    for (e, mut stone_pos, mut vel_vec, active) in stones.iter_mut() {
+        if active {
+            if let Ok(mut spot_pos) = spotty.get_single_mut() {
+                spot_pos.translation = stone_pos.translation + Vec3::new(1.0, STONE_RADIUS * 2.0, 1.0);
+            }
+        }
 
-    let x_dist = stone_pos.translation.x.abs();
-    let y_dist = stone_pos.translation.y;
-    if x_dist > CHUNK_SIZE || y_dist < -STONE_RADIUS * 12.0 {
-        // TODO: this should transition to phase?
-        // don't think this is needed anymore?
-        // is it reset after fall off edge?
-        stone_pos.translation = Vec3::new(STONE_X, STONE_Y, STONE_Z);
-        vel_vec.x = 0.0;
-        vel_vec.y = 0.0;
-        vel_vec.z = 0.0;
+        let sheet_pos = WorldPos(stone_pos.translation).to_sheet().0;
+        let off_side = !(0.0..=SHEET_WIDTH).contains(&sheet_pos.x);
+        if off_side || sheet_pos.y < -STONE_RADIUS * 12.0 {
+            if !active {
+                commands.entity(e).despawn_recursive();
+                continue;
+            }
+            // TODO: this should transition to phase?
+            // don't think this is needed anymore?
+            // is it reset after fall off edge?
+            stone_pos.translation = Vec3::new(STONE_X, STONE_Y, STONE_Z);
+            vel_vec.x = 0.0;
+            vel_vec.y = 0.0;
+            vel_vec.z = 0.0;
+        }
     }
 }
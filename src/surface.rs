@@ -0,0 +1,294 @@
+use std::ops::Range;
+
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use noise::{NoiseFn, Perlin};
+use rand::prelude::*;
+
+use crate::color_ramp::ColorRamp;
+use crate::constants::{MAX_TERRAIN_HEIGHT, SWEEP_FRICTION};
+use crate::game::GameState;
+use crate::height_map::{DirtyRect, HeightMap};
+use crate::coords::WorldPos;
+use crate::stone::Stone;
+use crate::sweep::Sweep;
+
+/// What the ground is made of. Sets how the stone slides and bounces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Surface {
+    #[default]
+    Grass,
+    Ice,
+    Snow,
+    Sand,
+    Mud,
+}
+
+impl Surface {
+    pub const ALL: [Surface; 5] = [
+        Surface::Grass,
+        Surface::Ice,
+        Surface::Snow,
+        Surface::Sand,
+        Surface::Mud,
+    ];
+
+    pub fn friction(&self) -> f32 {
+        match self {
+            Surface::Grass => 1.0,
+            Surface::Ice => 0.05,
+            Surface::Snow => 0.6,
+            Surface::Sand => 3.0,
+            Surface::Mud => 5.0,
+        }
+    }
+
+    pub fn restitution(&self) -> f32 {
+        match self {
+            Surface::Grass => 0.0,
+            Surface::Ice => 0.3,
+            Surface::Snow => 0.0,
+            Surface::Sand => 0.0,
+            Surface::Mud => 0.0,
+        }
+    }
+
+    /// How much the stone sinks in: 0 is hard ground, 1 is the softest
+    pub fn softness(&self) -> f32 {
+        match self {
+            Surface::Grass => 0.0,
+            Surface::Ice => 0.0,
+            Surface::Snow => 0.5,
+            Surface::Sand => 0.7,
+            Surface::Mud => 1.0,
+        }
+    }
+
+    /// Color of this surface at height `h`, before shading
+    pub fn color(&self, ramp: &ColorRamp, h: f32) -> LinearRgba {
+        let col = match self {
+            Surface::Grass => return ramp.height_color(h),
+            Surface::Ice => Color::srgb(0.7, 0.9, 1.0),
+            Surface::Snow => Color::srgb(0.95, 0.95, 1.0),
+            Surface::Sand => Color::srgb(0.9, 0.8, 0.5),
+            Surface::Mud => Color::srgb(0.35, 0.25, 0.1),
+        };
+        col.to_linear()
+    }
+
+    /// The next surface, for cycling through them
+    pub fn next(&self) -> Surface {
+        let i = Surface::ALL.iter().position(|s| s == self).unwrap_or(0);
+        Surface::ALL[(i + 1) % Surface::ALL.len()]
+    }
+}
+
+/// Surface of every cell on the sheet. Same layout as the HeightMap.
+#[derive(Resource, Clone, Debug)]
+pub struct SurfaceMap {
+    pub cell_w: usize,
+    pub cell_h: usize,
+    first_row: usize, // rows before this have been dropped, like the HeightMap
+    map: Vec<Surface>,
+    dirty: Option<DirtyRect>, // cells to recolour, their heights haven't moved
+}
+
+impl SurfaceMap {
+    /// All grass
+    pub fn new(cell_w: usize, cell_h: usize) -> Self {
+        SurfaceMap {
+            cell_w,
+            cell_h,
+            first_row: 0,
+            map: vec![Surface::Grass; cell_w * cell_h],
+            dirty: None,
+        }
+    }
+
+    /// Snow on the peaks, some winding ice lanes and sand traps
+    pub fn generate(hm: &HeightMap) -> Self {
+        let mut sm = SurfaceMap::new(hm.cell_w, hm.cell_h);
+        sm.scatter(hm, 0..hm.cell_h, 3, 12, &mut hm.rng(3));
+        sm
+    }
+
+    /// Grow to match a height map that has been extended. The new rows
+    /// get snow, ice and sand like a new sheet does, from the seed.
+    pub fn extend(&mut self, hm: &HeightMap) {
+        let rows = self.cell_h..hm.cell_h;
+        if rows.is_empty() {
+            return;
+        }
+        self.map.resize(self.map.len() + rows.len() * self.cell_w, Surface::Grass);
+        self.cell_h = hm.cell_h;
+        // Streamed a chunk at a time, so a lane and a few traps each
+        let mut rng = hm.rng(((rows.start as u64) << 8) | 3);
+        self.scatter(hm, rows, 1, 3, &mut rng);
+    }
+
+    /// Snow on the peaks in `rows`, with `lanes` ice lanes wandering down
+    /// them and `traps` sand traps (and the odd mud patch) in the low bits
+    fn scatter(
+        &mut self,
+        hm: &HeightMap,
+        rows: Range<usize>,
+        lanes: usize,
+        traps: usize,
+        rng: &mut impl Rng
+    ) {
+        for y in rows.clone() {
+            for x in 0..hm.cell_w {
+                if hm.get(x, y) > MAX_TERRAIN_HEIGHT * 0.9 {
+                    self.set(x, y, Surface::Snow);
+                }
+            }
+        }
+
+        // Ice lanes wander down the sheet
+        let noise = Perlin::new(hm.seed);
+        let len = rows.len();
+        for lane in 0..lanes {
+            let start = rows.start + rng.random_range(0..len / 2);
+            let lane_len = rng.random_range(len / 8..len / 3);
+            let centre = rng.random_range(0.25..0.75) * hm.cell_w as f32;
+            let width = rng.random_range(2..5);
+            for y in start..(start + lane_len).min(rows.end) {
+                let wander = noise.get([y as f64 * 0.02, lane as f64 * 10.0, 0.0]) as f32;
+                let x = (centre + wander * hm.cell_w as f32 * 0.3) as usize;
+                for x in x.saturating_sub(width)..=x + width {
+                    self.set(x, y, Surface::Ice);
+                }
+            }
+        }
+
+        // Sand traps sit in the low bits, with the odd mud patch
+        for i in 0..traps {
+            let Some((cx, cy)) = low_cell(hm, rows.clone(), rng) else { continue; };
+            if cy < hm.cell_h / 10 {
+                // Keep the launch area clear
+                continue;
+            }
+            let surface = if i % 4 == 0 { Surface::Mud } else { Surface::Sand };
+            let r = rng.random_range(3..8);
+            self.paint(cx, cy, r, surface);
+        }
+    }
+
+    /// Forget the rows before `first_row`, see `HeightMap::drop_rows`
+    pub fn drop_rows(&mut self, first_row: usize) {
+        let first_row = first_row.min(self.cell_h);
+        if first_row <= self.first_row {
+            return;
+        }
+        self.map.drain(..(first_row - self.first_row) * self.cell_w);
+        self.first_row = first_row;
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Surface {
+        let y = y.max(self.first_row) - self.first_row;
+        self.map[y * self.cell_w + x]
+    }
+
+    /// Returns true if the cell changed
+    pub fn set(&mut self, x: usize, y: usize, surface: Surface) -> bool {
+        if x >= self.cell_w || y >= self.cell_h || y < self.first_row {
+            return false;
+        }
+        let cell = &mut self.map[(y - self.first_row) * self.cell_w + x];
+        let changed = *cell != surface;
+        *cell = surface;
+        changed
+    }
+
+    /// Set every cell within `r` cells of x/y. Returns the cells that
+    /// changed, with what they were before.
+    pub fn paint(&mut self, x: usize, y: usize, r: usize, surface: Surface) -> Vec<(usize, usize, Surface)> {
+        let mut changed = vec![];
+        for j in y.saturating_sub(r)..=y + r {
+            for i in x.saturating_sub(r)..=x + r {
+                let dist = Vec2::new(i as f32 - x as f32, j as f32 - y as f32).length();
+                if dist > r as f32 || i >= self.cell_w || j >= self.cell_h {
+                    continue;
+                }
+                let before = self.get(i, j);
+                if self.set(i, j, surface) {
+                    changed.push((i, j, before));
+                }
+            }
+        }
+        changed
+    }
+
+    /// Cells that need recolouring. Unlike the HeightMap's, these don't
+    /// need the colliders rebuilt.
+    pub fn mark_dirty(&mut self, rect: DirtyRect) {
+        self.dirty = Some(match self.dirty {
+            Some(dirty) => dirty.union(&rect),
+            None => rect,
+        });
+    }
+
+    /// Every cell still held needs recolouring, eg. for a new theme
+    pub fn mark_all_dirty(&mut self) {
+        self.mark_dirty(DirtyRect {
+            min_x: 0,
+            min_y: self.first_row,
+            max_x: self.cell_w - 1,
+            max_y: self.cell_h - 1,
+        });
+    }
+
+    /// Cells marked since this was last called
+    pub fn take_dirty(&mut self) -> Option<DirtyRect> {
+        self.dirty.take()
+    }
+}
+
+/// A random cell in `rows` between 0 and 1 metres high, if one turns up
+fn low_cell(hm: &HeightMap, rows: Range<usize>, rng: &mut impl Rng) -> Option<(usize, usize)> {
+    for _ in 0..100 {
+        let cell = (rng.random_range(0..hm.cell_w), rng.random_range(rows.clone()));
+        let h = hm.get(cell.0, cell.1);
+        if (0.0..=1.0).contains(&h) {
+            return Some(cell);
+        }
+    }
+    None
+}
+
+pub fn surface_plugin(app: &mut App) {
+    app.add_systems(
+        FixedUpdate,
+        surface_under_stone.run_if(in_state(GameState::InGame))
+    );
+}
+
+/// The chunks have one collider each, so each stone carries the
+/// friction and bounce of the surface it's on. Its combine rules
+/// win over the terrain's, so it's what you get on contact.
+/// Swept ground is slicker, see sweep.rs.
+fn surface_under_stone(
+    mut stones: Query<(&Transform, &mut Friction, &mut Restitution), With<Stone>>,
+    height_map: Option<Res<HeightMap>>,
+    surface_map: Option<Res<SurfaceMap>>,
+    sweep: Res<Sweep>,
+) {
+    let (Some(height_map), Some(surface_map)) = (height_map, surface_map) else { return; };
+    for (t, mut friction, mut restitution) in stones.iter_mut() {
+        let surface = WorldPos(t.translation)
+            .to_cell(&height_map)
+            .map(|c| surface_map.get(c.x, c.y))
+            .unwrap_or_default();
+
+        let swept = sweep.covers(WorldPos(t.translation).to_sheet().xz());
+        let coefficient = surface.friction() * if swept { SWEEP_FRICTION } else { 1.0 };
+        if friction.dynamic_coefficient != coefficient {
+            *friction = Friction::new(coefficient)
+                .with_combine_rule(CoefficientCombine::Multiply);
+        }
+        if restitution.coefficient != surface.restitution() {
+            *restitution = Restitution::new(surface.restitution())
+                .with_combine_rule(CoefficientCombine::Max);
+        }
+    }
+}
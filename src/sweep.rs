@@ -0,0 +1,163 @@
+use avian3d::prelude::*;
+use bevy::{
+    color::palettes::css::AQUA,
+    prelude::*,
+};
+
+use crate::constants::{
+    STONE_DAMPENING,
+    STONE_STOP_VEL,
+    SWEEP_AHEAD,
+    SWEEP_DAMPING,
+    SWEEP_PATCH_TIME,
+    SWEEP_RADIUS,
+    SWEEP_RECOVER,
+    SWEEP_STAMINA,
+};
+use crate::coords::{SheetPos, WorldPos};
+use crate::game::{GamePhase, OnThrowScreen};
+use crate::height_map::HeightMap;
+use crate::stone::ActiveStone;
+
+/// Sweeping the ground in front of the stone during a throw. It's
+/// part of the game, so it's kept here and never counts as cheating.
+#[derive(Resource, Clone, Debug)]
+pub struct Sweep {
+    pub stamina: f32, // seconds of sweeping left
+    pub swept: f32, // seconds swept this throw
+    patches: Vec<(Vec2, f32)>, // SHEET position swept, and seconds it stays slick
+}
+
+impl Default for Sweep {
+    fn default() -> Self {
+        Self {
+            stamina: SWEEP_STAMINA,
+            swept: 0.0,
+            patches: vec![],
+        }
+    }
+}
+
+#[derive(Component)]
+struct TextStamina;
+
+impl Sweep {
+    /// Sweep for `dt` seconds, ahead of a stone at SHEET position `pos`
+    /// heading in `dir`. Returns false when there's no stamina left.
+    pub fn sweep(&mut self, pos: Vec2, dir: Vec2, dt: f32) -> bool {
+        if self.stamina <= 0.0 {
+            return false;
+        }
+        self.stamina = (self.stamina - dt).max(0.0);
+        self.swept += dt;
+        self.patches.push((pos + dir.normalize_or_zero() * SWEEP_AHEAD, SWEEP_PATCH_TIME));
+        true
+    }
+
+    /// Let swept patches wear off, and get some stamina back if resting
+    pub fn tick(&mut self, dt: f32, resting: bool) {
+        for patch in self.patches.iter_mut() {
+            patch.1 -= dt;
+        }
+        self.patches.retain(|p| p.1 > 0.0);
+        if resting {
+            self.stamina = (self.stamina + SWEEP_RECOVER * dt).min(SWEEP_STAMINA);
+        }
+    }
+
+    /// Whether SHEET position `p` has been swept
+    pub fn covers(&self, p: Vec2) -> bool {
+        self.patches.iter().any(|(c, _)| c.distance(p) < SWEEP_RADIUS)
+    }
+}
+
+pub fn sweep_plugin(app: &mut App) {
+    app.init_resource::<Sweep>();
+    app.add_systems(OnEnter(GamePhase::Sculpting), setup_sweep);
+    app.add_systems(Update, (
+        sweep_ahead,
+        draw_sweep,
+        text_stamina,
+    ).run_if(in_state(GamePhase::Sculpting)));
+    app.add_systems(
+        FixedUpdate,
+        swept_damping.run_if(in_state(GamePhase::Sculpting))
+    );
+}
+
+fn setup_sweep(mut commands: Commands) {
+    commands.insert_resource(Sweep::default());
+
+    commands.spawn((
+        TextFont {
+            font_size: 18.0,
+            ..default()
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(62.0),
+            left: Val::Px(5.0),
+            ..default()
+        },
+        OnThrowScreen,
+    ))
+        .with_child( Text::new("Sweep (space):"))
+        .with_child((
+            Text::new(""),
+            TextStamina
+        ));
+}
+
+/// Hold space to sweep in front of the moving stone
+fn sweep_ahead(
+    keys: Res<ButtonInput<KeyCode>>,
+    stone: Query<(&Transform, &LinearVelocity), With<ActiveStone>>,
+    mut sweep: ResMut<Sweep>,
+    time: Res<Time>,
+) {
+    let Ok((t, vel)) = stone.get_single() else { return; };
+    let dt = time.delta_secs();
+    let moving = vel.0.xz().length() > STONE_STOP_VEL;
+    let sweeping = keys.pressed(KeyCode::Space) && moving;
+    if sweeping {
+        let pos = WorldPos(t.translation).to_sheet().xz();
+        sweep.sweep(pos, vel.0.xz(), dt);
+    }
+    sweep.tick(dt, !sweeping);
+}
+
+/// Swept ground slows the stone less. Friction is done with the
+/// surface's in surface.rs.
+fn swept_damping(
+    mut stone: Query<(&Transform, &mut LinearDamping), With<ActiveStone>>,
+    sweep: Res<Sweep>,
+) {
+    let Ok((t, mut damping)) = stone.get_single_mut() else { return; };
+    let swept = sweep.covers(WorldPos(t.translation).to_sheet().xz());
+    let want = if swept { STONE_DAMPENING * SWEEP_DAMPING } else { STONE_DAMPENING };
+    if damping.0 != want {
+        damping.0 = want;
+    }
+}
+
+fn draw_sweep(
+    sweep: Res<Sweep>,
+    height_map: Res<HeightMap>,
+    mut gizmos: Gizmos,
+) {
+    let flat = Quat::from_rotation_x(std::f32::consts::FRAC_PI_2);
+    for (p, _) in sweep.patches.iter() {
+        let Some(h) = height_map.sample_height(p.x, p.y) else { continue; };
+        let centre = SheetPos::new(p.x, h + 1.0, p.y).to_world().0;
+        gizmos.circle(Isometry3d::new(centre, flat), SWEEP_RADIUS, AQUA);
+    }
+}
+
+fn text_stamina(
+    mut txt: Query<&mut Text, With<TextStamina>>,
+    sweep: Res<Sweep>,
+) {
+    for mut span in txt.iter_mut() {
+        span.0 = format!(" {:.0}%", sweep.stamina / SWEEP_STAMINA * 100.0);
+    }
+}
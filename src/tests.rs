@@ -1,12 +1,48 @@
 #[cfg(test)]
 mod tests {
     // use super::*;
-    use crate::height_map::HeightMap;
+    use std::time::Instant;
+    use bevy::prelude::*;
+    use bevy::render::mesh::{MeshVertexAttribute, VertexAttributeValues};
+    use crate::brush::{BrushKind, SculptBrush};
+    use crate::chunk::{
+        chunk_at,
+        chunk_mesh,
+        chunk_translation,
+        chunks_with_cols,
+        chunks_with_rows,
+        sync_chunk_rect,
+        sync_chunk_with_heightmap,
+    };
+    use crate::color_ramp::{ColorRamp, THEMES};
+    use crate::constants::{
+        CELL_SIZE,
+        CHUNK_SIZE,
+        NUM_CHUNK_COLS,
+        SHEET_WIDTH,
+        SWEEP_AHEAD,
+        SWEEP_PATCH_TIME,
+        SWEEP_STAMINA,
+    };
+    use crate::erosion::ErosionSettings;
+    use crate::game::best_distance;
+    use crate::generator::{Endless, SheetGenerators};
+    use crate::height_map::{DirtyRect, HeightMap};
+    use crate::impact::crater_stamp;
+    use crate::history::SculptHistory;
+    use crate::roll_line::RollSettings;
+    use crate::spin::Spin;
+    use crate::surface::{Surface, SurfaceMap};
+    use crate::sweep::Sweep;
+    use crate::coords::{CellIdx, SheetPos, WorldPos};
     use crate::sheet::get_neighbours_radius;
+    use crate::stamp::{BlendMode, Stamp, StampShape};
+    use crate::water::{WaterMap, WaterSettings};
+    use crate::zone::{Zone, ZoneRule};
     
     #[test]
     fn pos_to_cell() {
-        let mut height_map = HeightMap::new(100.0, 100.0, 10, 10);
+        let mut height_map = HeightMap::new(100.0, 100.0, 10, 10, 1);
 
         let cell = height_map.get_cell_from_pos(0.0, 0.0);
         assert_eq!(cell, Some((0, 0)));
@@ -14,24 +50,735 @@ mod tests {
         let cell = height_map.get_cell_from_pos(50.0, 50.0);
         assert_eq!(cell, Some((5, 5)));
 
-        height_map.map[0][0] = 0.5;
+        height_map.set_height(0, 0, 0.5);
         let h = height_map.pos_to_height(0.0, 0.0);
         assert_eq!(h, Some(0.5));
 
-        height_map.map[1][1] = 2.0;                
+        height_map.set_height(1, 1, 2.0);                
         let h = height_map.pos_to_height(10.0, 10.0);
         assert_eq!(h, Some(2.0));
 
-        height_map.map[2][9] = 2.5;                
+        height_map.set_height(9, 2, 2.5);                
         let h = height_map.pos_to_height(95.0, 25.0);
         assert_eq!(h, Some(2.5));
 
         let h = height_map.pos_to_height(100.1, 0.0);
         assert_eq!(h, None);
 
-        // Should fail I guess...
-        //let h = height_map.pos_to_height(-0.1, 0.0);
-        //assert_eq!(h, None);
+        let h = height_map.pos_to_height(-0.1, 0.0);
+        assert_eq!(h, None);
+    }
+
+    #[test]
+    fn sample_between_cells() {
+        let mut hm = HeightMap::flat(100.0, 100.0, 10, 10, 1);
+        for y in 0..10 {
+            hm.set_height(1, y, 1.0);
+        }
+
+        assert_eq!(hm.sample_height(0.0, 0.0), Some(0.0));
+        assert_eq!(hm.sample_height(5.0, 0.0), Some(0.5));
+        assert_eq!(hm.sample_height(5.0, 5.0), Some(0.5));
+        assert_eq!(hm.sample_height(-0.1, 5.0), None);
+        assert_eq!(hm.sample_height(5.0, -0.1), None);
+        assert_eq!(hm.sample_normal(-1.0, 0.0), None);
+
+        // Rising along x, so the normal leans back along -x
+        let n = hm.sample_normal(5.0, 5.0).unwrap();
+        assert!(n.x < 0.0 && n.y > 0.0 && n.z.abs() < 0.001);
+        assert!(hm.slope_at(5.0, 5.0).unwrap() > 0.0);
+        assert_eq!(hm.slope_at(80.0, 80.0), Some(0.0));
+    }
+
+    #[test]
+    fn same_seed_same_sheet() {
+        let a = HeightMap::new(100.0, 400.0, 20, 80, 1234);
+        let b = HeightMap::new(100.0, 400.0, 20, 80, 1234);
+        assert_eq!(a.heights(), b.heights());
+
+        let c = HeightMap::new(100.0, 400.0, 20, 80, 4321);
+        assert_ne!(a.heights(), c.heights());
+    }
+
+    #[test]
+    fn flatten_brush() {
+        let hm = HeightMap::new(100.0, 100.0, 20, 20, 1);
+        let brush = SculptBrush::new(BrushKind::Flatten, 2, 0.5);
+        for (x, y, d) in brush.deltas(&hm, (10, 10), Vec2::Y, 3.0, false) {
+            let cur = hm.get(x, y);
+            assert!(d.abs() <= (3.0 - cur).abs());
+            assert!(d == 0.0 || d.signum() == (3.0 - cur).signum());
+        }
+    }
+
+    #[test]
+    fn soil_budget() {
+        let mut hm = HeightMap::new(100.0, 100.0, 10, 10, 1);
+        hm.set_height(5, 5, 2.0);
+        hm.soil = Some(0.0);
+
+        // Can't build with nothing dug
+        assert_eq!(hm.add_height(4, 4, 1.0), 0.0);
+
+        // Dig 1m from a 10x10 cell, then spend it
+        assert_eq!(hm.add_height(5, 5, -1.0), -1.0);
+        assert_eq!(hm.soil, Some(100.0));
+        assert_eq!(hm.add_height(4, 4, 1.0), 1.0);
+        assert_eq!(hm.soil, Some(0.0));
+    }
+
+    #[test]
+    fn undo_redo_stroke() {
+        let mut hm = HeightMap::new(100.0, 100.0, 10, 10, 1);
+        hm.set_height(2, 3, 1.0);
+        let mut sm = SurfaceMap::new(10, 10);
+        let mut history = SculptHistory::default();
+
+        history.begin_stroke();
+        hm.set_height(2, 3, 1.5);
+        history.record(2, 3, 1.0, 1.5);
+        hm.set_height(2, 3, 2.0);
+        history.record(2, 3, 1.5, 2.0);
+        history.end_stroke();
+
+        assert_eq!(history.undo(&mut hm, &mut sm), Some((3, 3)));
+        assert_eq!(hm.get(2, 3), 1.0);
+        assert_eq!(history.undo(&mut hm, &mut sm), None);
+
+        assert_eq!(history.redo(&mut hm, &mut sm), Some((3, 3)));
+        assert_eq!(hm.get(2, 3), 2.0);
+    }
+
+    #[test]
+    fn undo_keeps_craters() {
+        let mut hm = HeightMap::new(100.0, 100.0, 10, 10, 1);
+        hm.set_height(2, 3, 1.0);
+        let mut sm = SurfaceMap::new(10, 10);
+        let mut history = SculptHistory::default();
+
+        history.begin_stroke();
+        hm.set_height(2, 3, 2.0);
+        history.record(2, 3, 1.0, 2.0);
+        history.end_stroke();
+
+        // The stone digs in after the stroke, which isn't in the history
+        hm.add_height(2, 3, -0.5);
+        history.undo(&mut hm, &mut sm);
+        assert_eq!(hm.get(2, 3), 0.5);
+        history.redo(&mut hm, &mut sm);
+        assert_eq!(hm.get(2, 3), 1.5);
+    }
+
+    #[test]
+    fn undo_paint() {
+        let mut hm = HeightMap::flat(100.0, 100.0, 10, 10, 1);
+        let mut sm = SurfaceMap::new(10, 10);
+        let mut history = SculptHistory::default();
+
+        history.begin_stroke();
+        hm.set_height(2, 2, 1.0);
+        history.record(2, 2, 0.0, 1.0);
+        history.end_stroke();
+
+        history.begin_stroke();
+        for (x, y, before) in sm.paint(5, 5, 1, Surface::Ice) {
+            history.record_surface(x, y, before, Surface::Ice);
+        }
+        history.end_stroke();
+
+        // Undoing the paint leaves the heights alone
+        assert_eq!(history.undo(&mut hm, &mut sm), Some((4, 6)));
+        assert_eq!(sm.get(5, 5), Surface::Grass);
+        assert_eq!(hm.get(2, 2), 1.0);
+        assert!(sm.take_dirty().is_some());
+
+        assert_eq!(history.redo(&mut hm, &mut sm), Some((4, 6)));
+        assert_eq!(sm.get(5, 6), Surface::Ice);
+    }
+
+    #[test]
+    fn undo_needs_soil() {
+        let mut hm = HeightMap::new(100.0, 100.0, 10, 10, 1);
+        hm.set_height(5, 5, 2.0);
+        hm.soil = Some(0.0);
+        let mut sm = SurfaceMap::new(10, 10);
+        let mut history = SculptHistory::default();
+
+        history.begin_stroke();
+        let dug = hm.add_height(5, 5, -1.0);
+        history.record(5, 5, 2.0, 2.0 + dug);
+        history.end_stroke();
+
+        // Spend what was dug, then undoing the dig can't be paid for
+        assert_eq!(hm.add_height(4, 4, 1.0), 1.0);
+        assert_eq!(history.undo(&mut hm, &mut sm), None);
+        assert_eq!(hm.get(5, 5), 1.0);
+        assert_eq!(hm.soil, Some(0.0));
+
+        // It can once the soil is back
+        assert_eq!(hm.add_height(4, 4, -1.0), -1.0);
+        assert_eq!(history.undo(&mut hm, &mut sm), Some((5, 5)));
+        assert_eq!(hm.get(5, 5), 2.0);
+        assert_eq!(hm.soil, Some(0.0));
+    }
+
+    #[test]
+    fn dirty_rect() {
+        let mut hm = HeightMap::flat(100.0, 100.0, 10, 10, 1);
+        assert_eq!(hm.take_dirty(), None);
+
+        hm.add_height(2, 3, 1.0);
+        hm.add_height(6, 1, 1.0);
+        hm.add_height(4, 4, 0.0);
+        assert_eq!(hm.take_dirty(), Some(DirtyRect { min_x: 2, min_y: 1, max_x: 6, max_y: 3 }));
+        assert_eq!(hm.take_dirty(), None);
+    }
+
+    #[test]
+    fn partial_sync_matches_full_sync() {
+        let mut hm = HeightMap::new(CHUNK_SIZE, CHUNK_SIZE, CELL_SIZE, CELL_SIZE, 3);
+        let mut sm = SurfaceMap::generate(&hm);
+        let ramp = ColorRamp::default();
+        let mut partial = chunk_mesh();
+        sync_chunk_with_heightmap(&mut partial, &hm, &sm, &ramp, 0, 0);
+
+        let brush = SculptBrush::new(BrushKind::Raise, 4, 1.0);
+        for (x, y, d) in brush.deltas(&hm, (60, 70), Vec2::Y, 0.0, false) {
+            hm.add_height(x, y, d);
+        }
+        sm.paint(30, 40, 3, Surface::Mud);
+        sm.mark_dirty(DirtyRect::cell(30, 40).grow(3));
+        for rect in [hm.take_dirty().unwrap(), sm.take_dirty().unwrap()] {
+            sync_chunk_rect(&mut partial, &hm, &sm, &ramp, 0, 0, rect);
+        }
+
+        let mut full = chunk_mesh();
+        sync_chunk_with_heightmap(&mut full, &hm, &sm, &ramp, 0, 0);
+        for attr in [Mesh::ATTRIBUTE_POSITION, Mesh::ATTRIBUTE_NORMAL, Mesh::ATTRIBUTE_COLOR] {
+            assert_eq!(
+                partial.attribute(attr.id).unwrap().get_bytes(),
+                full.attribute(attr.id).unwrap().get_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn chunks_share_edge_rows() {
+        let hm = HeightMap::new(CHUNK_SIZE, CHUNK_SIZE * 2.0, CELL_SIZE, CELL_SIZE * 2, 5);
+        let sm = SurfaceMap::new(hm.cell_w, hm.cell_h);
+        let ramp = ColorRamp::default();
+        let mut near = chunk_mesh();
+        let mut far = chunk_mesh();
+        sync_chunk_with_heightmap(&mut near, &hm, &sm, &ramp, 0, 0);
+        sync_chunk_with_heightmap(&mut far, &hm, &sm, &ramp, 0, CELL_SIZE as i32);
+
+        // Last row of the near chunk is the first row of the far one
+        let verts = CELL_SIZE + 1;
+        let attr = |mesh: &Mesh, attr: MeshVertexAttribute| {
+            mesh.attribute(attr).unwrap().as_float3().unwrap().to_vec()
+        };
+        let (near_pos, far_pos) = (attr(&near, Mesh::ATTRIBUTE_POSITION), attr(&far, Mesh::ATTRIBUTE_POSITION));
+        let (near_norm, far_norm) = (attr(&near, Mesh::ATTRIBUTE_NORMAL), attr(&far, Mesh::ATTRIBUTE_NORMAL));
+        for x in 0..verts {
+            assert_eq!(near_pos[CELL_SIZE * verts + x][1], far_pos[x][1]);
+            assert_eq!(near_norm[CELL_SIZE * verts + x], far_norm[x]);
+        }
+
+        let rows = |min_y, max_y| chunks_with_rows(DirtyRect { min_x: 0, min_y, max_x: 0, max_y });
+        assert_eq!(rows(0, 5), 0..=0);
+        assert_eq!(rows(CELL_SIZE, CELL_SIZE), 0..=1);
+        assert_eq!(rows(CELL_SIZE + 1, CELL_SIZE * 2 - 1), 1..=1);
+    }
+
+    #[test]
+    fn wide_sheet_layout() {
+        for pos in [IVec2::new(0, 0), IVec2::new(NUM_CHUNK_COLS - 1, 3), IVec2::new(1, 13)] {
+            assert_eq!(chunk_at(chunk_translation(pos)), pos);
+        }
+        // Middle chunk (or the only one) sits on the stone's line
+        assert_eq!(chunk_translation(IVec2::new(NUM_CHUNK_COLS / 2, 0)).x.abs() % CHUNK_SIZE, 0.0);
+
+        // Edge columns are in both chunks
+        let rect = DirtyRect::cell(CELL_SIZE, 0);
+        assert_eq!(chunks_with_cols(rect), 0..=1);
+        assert_eq!(chunks_with_cols(rect.grow(2)), 0..=1);
+        assert_eq!(chunks_with_cols(DirtyRect::cell(5, 0)), 0..=0);
+    }
+
+    #[test]
+    fn coords_round_trip() {
+        // Sheet is centred across the world x axis, starting half a chunk back
+        let start = WorldPos(Vec3::new(-SHEET_WIDTH * 0.5, 0.0, -CHUNK_SIZE * 0.5));
+        assert_eq!(start.to_sheet(), SheetPos::default());
+
+        let world = WorldPos(Vec3::new(123.0, 4.0, 567.0));
+        assert_eq!(world.to_sheet().to_world(), world);
+        let sheet = SheetPos::new(50.0, 2.0, 1234.5);
+        assert_eq!(sheet.to_world().to_sheet(), sheet);
+        assert_eq!(sheet.to_world().0.y, 2.0);
+
+        // 10m cells
+        let mut hm = HeightMap::flat(100.0, 200.0, 10, 20, 1);
+        hm.set_height(3, 7, 4.0);
+        let cell = CellIdx::new(3, 7);
+        assert_eq!(cell.to_sheet(&hm), SheetPos::new(30.0, 4.0, 70.0));
+        assert_eq!(cell.to_sheet(&hm).to_cell(&hm), Some(cell));
+        assert_eq!(cell.to_world(&hm).to_cell(&hm), Some(cell));
+
+        // Anywhere in a cell is that cell, off the sheet is nothing
+        assert_eq!(SheetPos::new(39.9, 0.0, 79.9).to_cell(&hm), Some(cell));
+        assert_eq!(SheetPos::new(-1.0, 0.0, 10.0).to_cell(&hm), None);
+        assert_eq!(SheetPos::new(10.0, 0.0, 200.0).to_cell(&hm), None);
+        assert_eq!(SheetPos::new(35.0, 9.0, 70.0).ground(&hm), Some(2.0));
+
+        let t: (usize, usize) = cell.into();
+        assert_eq!(CellIdx::from(t), cell);
+    }
+
+    #[test]
+    fn surface_map() {
+        let hm = HeightMap::new(CHUNK_SIZE, CHUNK_SIZE * 4.0, CELL_SIZE, CELL_SIZE * 4, 8);
+        let a = SurfaceMap::generate(&hm);
+        let b = SurfaceMap::generate(&hm);
+        let cells = |sm: &SurfaceMap| (0..sm.cell_h)
+            .flat_map(|y| (0..sm.cell_w).map(move |x| (x, y)))
+            .map(|(x, y)| sm.get(x, y))
+            .collect::<Vec<_>>();
+        assert_eq!(cells(&a), cells(&b));
+        assert!(cells(&a).contains(&Surface::Ice));
+
+        let mut sm = SurfaceMap::new(10, 10);
+        assert_eq!(sm.paint(5, 5, 2, Surface::Sand).len(), 13);
+        assert!(sm.paint(5, 5, 2, Surface::Sand).is_empty());
+        assert_eq!(sm.get(7, 5), Surface::Sand);
+        assert_eq!(sm.get(7, 7), Surface::Grass);
+        assert!(Surface::Ice.friction() < Surface::Grass.friction());
+    }
+
+    #[test]
+    fn erosion() {
+        let settings = ErosionSettings { droplets: 2000, ..Default::default() };
+        let fresh = HeightMap::new(CHUNK_SIZE, CHUNK_SIZE * 2.0, CELL_SIZE, CELL_SIZE * 2, 11);
+        let mut a = fresh.clone();
+        let mut b = fresh.clone();
+        a.erode(&settings);
+        b.erode(&settings);
+        assert_eq!(a.heights(), b.heights());
+        assert_ne!(a.heights(), fresh.heights());
+        assert!(a.heights().iter().all(|h| *h >= 0.0));
+
+        let mut off = fresh.clone();
+        off.erode(&ErosionSettings { enabled: false, ..settings.clone() });
+        assert_eq!(off.heights(), fresh.heights());
+
+        // Only the rows still held
+        let mut dropped = fresh.clone();
+        dropped.drop_rows(CELL_SIZE);
+        dropped.erode(&settings);
+        assert_eq!(dropped.heights().len(), CELL_SIZE * CELL_SIZE);
+        assert_ne!(dropped.heights(), &fresh.heights()[CELL_SIZE * CELL_SIZE..]);
+    }
+
+    #[test]
+    fn generators() {
+        let mut gens = SheetGenerators::default();
+        for _ in 0..gens.generators.len() {
+            let generator = gens.current();
+            let hm = HeightMap::generate(100.0, 400.0, 20, 80, 7, generator);
+            assert!(hm.heights().iter().all(|h| h.is_finite() && *h >= 0.0), "{}", generator.name());
+            gens.next();
+        }
+        assert_eq!(gens.current().name(), "classic");
+
+        let mut gens = SheetGenerators::from_spec("canyon:depth=40,width=0.3");
+        assert_eq!(gens.current().name(), "canyon");
+        assert!(!gens.rotate);
+        let params = gens.current_mut().params();
+        assert!(params.iter().any(|(n, v)| *n == "depth" && **v == 40.0));
+        assert!(!SheetGenerators::from_spec("nope").rotate);
+        assert!(!SheetGenerators::default().rotate);
+
+        let gens = SheetGenerators::from_spec("rotate");
+        assert!(gens.rotate);
+        assert_eq!(gens.current().name(), "classic");
+    }
+
+    #[test]
+    fn stamps() {
+        let mut hm = HeightMap::flat(100.0, 100.0, 40, 40, 1);
+        for y in 0..40 {
+            for x in 0..40 {
+                hm.set_height(x, y, 5.0);
+            }
+        }
+        hm.take_dirty();
+
+        // Ramp facing down the sheet rises along y
+        let ramp = Stamp::new(StampShape::Ramp, Vec2::new(4.0, 8.0), 4.0);
+        hm.apply_stamp(&ramp, (20, 20));
+        assert_eq!(hm.get(20, 12), 5.0);
+        assert!(hm.get(20, 27) > hm.get(20, 21));
+        assert!(hm.get(20, 27) <= 9.0);
+        assert_eq!(hm.get(26, 20), 5.0);
+        let dirty = hm.take_dirty().unwrap();
+        assert!(dirty.min_y >= ramp.bounds((20, 20)).min_y);
+
+        // Turned to face +x it rises along x instead
+        let turned = ramp.facing(Vec2::X).deltas(&hm, (10, 10));
+        let at = |x, y| turned.iter().find(|d| d.0 == x && d.1 == y).map_or(0.0, |d| d.2);
+        assert!(at(16, 10) > at(12, 10));
+        assert_eq!(at(10, 16), 0.0);
+
+        // Max never lowers, Min never raises
+        let bowl = Stamp::new(StampShape::Bowl, Vec2::splat(5.0), 3.0);
+        assert!(bowl.deltas(&hm, (10, 30)).iter().all(|d| d.2 <= 0.0));
+        let bowl = bowl.with_blend(BlendMode::Max);
+        assert!(bowl.deltas(&hm, (10, 30)).iter().all(|d| d.2 >= 0.0));
+
+        // Stamp brushes place their stamp
+        let mut brush = SculptBrush::new(BrushKind::Stamp(StampShape::Crater), 4, 2.0);
+        assert!(!brush.deltas(&hm, (30, 30), Vec2::Y, 0.0, false).is_empty());
+        // Scaled as a whole
+        brush.scale = 2.0;
+        let stamp = brush.stamp(Vec2::Y, false).unwrap();
+        assert_eq!((stamp.size, stamp.height), (Vec2::splat(8.0), 4.0));
+        // Turned and blended the brush's way
+        brush.rotation = 0.5;
+        brush.blend = Some(BlendMode::Max);
+        let stamp = brush.stamp(Vec2::Y, false).unwrap();
+        assert_eq!((stamp.rotation, stamp.blend), (0.5, BlendMode::Max));
+        assert_eq!(brush.stamp(Vec2::Y, true).unwrap().blend, BlendMode::Min);
+    }
+
+    #[test]
+    fn zones() {
+        // 1 metre cells
+        let mut hm = HeightMap::flat(40.0, 40.0, 40, 40, 1);
+        hm.zones = vec![
+            Zone::circle(Vec2::new(10.0, 10.0), 3.0, ZoneRule::DigOnly),
+            Zone::circle(Vec2::new(30.0, 10.0), 3.0, ZoneRule::BuildOnly),
+            Zone::rect(Vec2::new(0.0, 30.0), Vec2::new(40.0, 40.0), ZoneRule::Locked),
+        ];
+        for (x, y) in [(10, 10), (30, 10), (20, 35)] {
+            hm.set_height(x, y, 2.0);
+        }
+
+        assert_eq!(hm.add_height(10, 10, 1.0), 0.0);
+        assert_eq!(hm.add_height(10, 10, -1.0), -1.0);
+        assert_eq!(hm.add_height(30, 10, -1.0), 0.0);
+        assert_eq!(hm.add_height(30, 10, 1.0), 1.0);
+        assert_eq!(hm.add_height(20, 35, 1.0), 0.0);
+        assert_eq!(hm.add_height(20, 35, -1.0), 0.0);
+        assert!(hm.locked(20, 35));
+        assert!(!hm.locked(10, 10));
+
+        // Outside the zones anything goes
+        assert_eq!(hm.add_height(20, 20, 1.0), 1.0);
+        assert_eq!(hm.add_height(14, 10, 1.0), 1.0);
+    }
+
+    #[test]
+    fn impacts() {
+        let mut hm = HeightMap::flat(100.0, 100.0, 40, 40, 1);
+        for y in 0..40 {
+            for x in 0..40 {
+                hm.set_height(x, y, 5.0);
+            }
+        }
+        hm.soil = Some(10.0);
+        hm.take_dirty();
+
+        // Soft landings leave nothing
+        assert!(crater_stamp(10.0).is_none());
+        hm.impact((20, 20), 10.0);
+        assert!(hm.take_dirty().is_none());
+
+        // Harder landings leave bigger craters, without costing soil
+        let small = crater_stamp(60.0).unwrap();
+        let big = crater_stamp(120.0).unwrap();
+        assert!(big.size.x > small.size.x && big.height > small.height);
+        hm.impact((20, 20), 120.0);
+        assert!(hm.get(20, 20) < 5.0);
+        assert!(hm.take_dirty().unwrap().min_x >= big.bounds((20, 20)).min_x);
+        assert_eq!(hm.soil, Some(10.0));
+
+        // Grooves dig, deepest in the middle
+        hm.groove((5, 30), 0.2);
+        assert_eq!(hm.get(5, 30), 4.8);
+        assert!(hm.get(6, 30) < 5.0 && hm.get(6, 30) > 4.8);
+        assert_eq!(hm.get(7, 30), 5.0);
+    }
+
+    #[test]
+    fn endless_stream() {
+        let rows = CELL_SIZE;
+        let gen = Endless::default();
+        let full = HeightMap::generate(CHUNK_SIZE, CHUNK_SIZE * 3.0, CELL_SIZE, rows * 3, 7, &gen);
+
+        // Growing a chunk at a time gives the same heights as all at once
+        let mut hm = HeightMap::generate(CHUNK_SIZE, CHUNK_SIZE, CELL_SIZE, rows, 7, &gen);
+        let mut sm = SurfaceMap::new(hm.cell_w, hm.cell_h);
+        hm.extend(rows * 2, &gen);
+        sm.extend(&hm);
+        assert_eq!(hm.cell_h, full.cell_h);
+        assert_eq!(hm.h, full.h);
+        assert_eq!(hm.heights(), full.heights());
+        assert_eq!(sm.cell_h, hm.cell_h);
+        // Streamed rows aren't all grass and snow
+        assert!((rows..hm.cell_h).any(|y| (0..hm.cell_w).any(|x| sm.get(x, y) == Surface::Ice)));
+
+        // Dropped rows keep SHEET coords, and can't be found or sculpted
+        hm.drop_rows(rows);
+        sm.drop_rows(rows);
+        assert_eq!(hm.first_row(), rows);
+        assert_eq!(hm.heights().len(), CELL_SIZE * rows * 2);
+        assert_eq!(hm.get(70, rows + 10), full.get(70, rows + 10));
+        assert_eq!(hm.get(70, 5), full.get(70, rows));
+        assert_eq!(hm.get_cell_from_pos(200.0, 100.0), None);
+        assert!(hm.get_cell_from_pos(200.0, CHUNK_SIZE + 100.0).is_some());
+        assert_eq!(hm.add_height(70, 5, 1.0), 0.0);
+        assert_eq!(hm.add_height(70, rows + 10, 1.0), 1.0);
+        let other = sm.get(70, rows + 10).next();
+        assert!(!sm.set(70, 5, other));
+        assert!(sm.set(70, rows + 10, other));
+        assert_eq!(sm.get(70, rows + 10), other);
+    }
+
+    #[test]
+    fn roll_line() {
+        let settings = RollSettings::default();
+
+        // Flat: straight on, slowing down
+        let hm = HeightMap::flat(100.0, 1000.0, 50, 500, 1);
+        let path = hm.predict_roll(SheetPos::new(50.0, 0.0, 10.0), Vec2::new(0.0, 20.0), &settings);
+        assert!(path.len() > 10);
+        assert!(path.iter().all(|p| p.0.x == 50.0));
+        let steps: Vec<f32> = path.windows(2).map(|w| w[1].0.z - w[0].0.z).collect();
+        assert!(steps.windows(2).all(|s| s[1] < s[0]));
+
+        // Standing still on flat ground it goes nowhere
+        let path = hm.predict_roll(SheetPos::new(50.0, 0.0, 10.0), Vec2::ZERO, &settings);
+        assert_eq!(path.len(), 1);
+
+        // Sloping down the sheet and to the left (+x): rolls off downhill
+        let mut hm = HeightMap::flat(100.0, 100.0, 50, 50, 1);
+        for y in 0..50 {
+            for x in 0..50 {
+                hm.set_height(x, y, 100.0 - x as f32 - y as f32 * 0.5);
+            }
+        }
+        let path = hm.predict_roll(SheetPos::new(20.0, 0.0, 20.0), Vec2::ZERO, &settings);
+        let (first, last) = (path[0].0, path[path.len() - 1].0);
+        assert!(last.x > first.x && last.z > first.z && last.y < first.y);
+        assert!(path.iter().all(|p| p.0.x < 100.0 && p.0.z < 100.0));
+        assert!(path.len() < settings.max_steps);
+    }
+
+    #[test]
+    fn spin_curls() {
+        let down_sheet = Vec3::new(0.0, 0.0, 50.0);
+        assert_eq!(Spin(0.0).curl(down_sheet), Vec3::ZERO);
+        assert_eq!(Spin(1.0).curl(Vec3::ZERO), Vec3::ZERO);
+
+        // In-turn curls right (-x), out-turn left, and more when slower
+        let curl = Spin(1.0).curl(down_sheet);
+        assert!(curl.x < 0.0 && curl.y == 0.0 && curl.z.abs() < 1e-6);
+        assert_eq!(Spin(-1.0).curl(down_sheet), -curl);
+        assert!(Spin(1.0).curl(down_sheet * 0.2).length() > curl.length());
+
+        // And so does the predicted roll on flat ground
+        let hm = HeightMap::flat(200.0, 1000.0, 100, 500, 1);
+        let settings = RollSettings { spin: Spin(1.0), ..Default::default() };
+        let path = hm.predict_roll(SheetPos::new(100.0, 0.0, 10.0), Vec2::new(0.0, 40.0), &settings);
+        assert!(path[path.len() - 1].0.x < 90.0);
+    }
+
+    #[test]
+    fn sweeping() {
+        let mut sweep = Sweep::default();
+        let (pos, dir) = (Vec2::new(50.0, 50.0), Vec2::Y * 30.0);
+
+        // Swept in front of the stone, not behind it
+        assert!(sweep.sweep(pos, dir, 0.5));
+        assert!(sweep.covers(pos + Vec2::Y * SWEEP_AHEAD));
+        assert!(!sweep.covers(pos - Vec2::Y * SWEEP_AHEAD));
+        assert_eq!(sweep.swept, 0.5);
+
+        // Runs out of stamina, and gets it back resting
+        while sweep.sweep(pos, dir, 0.5) {}
+        assert_eq!(sweep.stamina, 0.0);
+        assert!(!sweep.sweep(pos, dir, 0.5));
+        assert_eq!(sweep.swept, SWEEP_STAMINA);
+        sweep.tick(1.0, true);
+        assert!(sweep.stamina > 0.0);
+
+        // Wears off
+        sweep.tick(SWEEP_PATCH_TIME, false);
+        assert!(!sweep.covers(pos + Vec2::Y * SWEEP_AHEAD));
+    }
+
+    #[test]
+    fn best_stone() {
+        let stones = [(120.0, false), (30.0, true), (80.0, false)];
+        // Closest in classic, furthest in endless, never a sunk one
+        assert_eq!(best_distance(stones, false), Some(80.0));
+        assert_eq!(best_distance(stones, true), Some(120.0));
+        assert_eq!(best_distance([(30.0, true)], false), None);
+    }
+
+    #[test]
+    fn water() {
+        // A bowl in a plateau, 2 metre cells
+        let mut hm = HeightMap::flat(100.0, 100.0, 50, 50, 1);
+        for y in 0..50 {
+            for x in 0..50 {
+                let d = Vec2::new(x as f32 - 25.0, y as f32 - 25.0).length();
+                hm.set_height(x, y, if d < 10.0 { 2.0 + d * 0.3 } else { 6.0 });
+            }
+        }
+
+        // Fills up to the rim, wherever it starts from
+        let mut water = WaterMap::new(hm.cell_w, hm.cell_h, 0.5);
+        assert!(water.add_lake(&hm, (30, 28), 8.0, 1000));
+        assert!(water.level(25, 25) <= 6.0 && water.level(25, 25) > 5.5);
+        assert!(water.depth(&hm, 25, 25) > 3.5);
+        assert_eq!(water.depth(&hm, 5, 5), 0.0);
+
+        // Too big to fit is no lake at all
+        let mut water = WaterMap::new(hm.cell_w, hm.cell_h, 0.5);
+        assert!(!water.add_lake(&hm, (25, 25), 8.0, 10));
+        assert_eq!(water.depth(&hm, 25, 25), 0.0);
+
+        // The sea doesn't come into the zones
+        hm.zones = vec![Zone::circle(Vec2::new(50.0, 50.0), 6.0, ZoneRule::DigOnly)];
+        let settings = WaterSettings { sea_level: 4.0, lakes: 0, rivers: 0, ..Default::default() };
+        let water = WaterMap::generate(&mut hm, &settings);
+        assert_eq!(water.depth(&hm, 25, 25), 0.0);
+        assert!(water.depth(&hm, 25, 30) > 0.0);
+    }
+
+    #[test]
+    fn color_ramp() {
+        let close = |a: [f32; 4], b: Color| {
+            Vec4::from_array(a).abs_diff_eq(Vec4::from_array(b.to_linear().to_f32_array()), 1e-5)
+        };
+
+        // The built in theme keeps the old height bands
+        let ramp = ColorRamp::default();
+        assert!(close(ramp.height_color(0.0).to_f32_array(), Color::srgb(0.26, 0.7, 0.119)));
+        assert!(close(ramp.height_color(12.0).to_f32_array(), Color::srgb(0.4, 0.9, 0.1)));
+        assert!(close(ramp.height_color(50.0).to_f32_array(), Color::srgb(1.0, 0.9, 1.0)));
+
+        // Flat ground is left alone, cliffs, ridges and gullies are tinted
+        let grass = ramp.height_color(3.0);
+        assert!(close(ramp.shade(grass, 0.0, 0.0), grass.into()));
+        assert!(!close(ramp.shade(grass, 3.0, 0.0), grass.into()));
+        assert!(!close(ramp.shade(grass, 0.0, 1.0), grass.into()));
+        assert!(!close(ramp.shade(grass, 0.0, -1.0), grass.into()));
+
+        for name in THEMES {
+            let path = format!("{}/assets/ramps/{}.ramp", env!("CARGO_MANIFEST_DIR"), name);
+            let text = std::fs::read_to_string(path).unwrap();
+            assert_eq!(ColorRamp::parse(&text).unwrap().name, name);
+        }
+    }
+
+    /// Strokes per second syncing a chunk the old way (every height and
+    /// color rewritten, then `compute_normals`) vs only the dirty cells.
+    /// `cargo test --release bench_sculpt_sync -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_sculpt_sync() {
+        let strokes = 500;
+        let brush = SculptBrush::new(BrushKind::Raise, 4, 0.1);
+        let mut mesh = chunk_mesh();
+        let mut hm = HeightMap::new(CHUNK_SIZE, CHUNK_SIZE, CELL_SIZE, CELL_SIZE, 1);
+        let sm = SurfaceMap::new(hm.cell_w, hm.cell_h);
+        let ramp = ColorRamp::default();
+        let stroke = |hm: &mut HeightMap, i: usize| {
+            let cell = (20 + i % 100, 20 + (i / 100) * 20);
+            for (x, y, d) in brush.deltas(hm, cell, Vec2::Y, 0.0, false) {
+                hm.add_height(x, y, d);
+            }
+        };
+
+        // How it was done before the dirty cells were tracked
+        let full_sync = |mesh: &mut Mesh, hm: &HeightMap| {
+            let verts = CELL_SIZE + 1;
+            let Some(VertexAttributeValues::Float32x3(vert_pos)) =
+                mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION) else {
+                panic!("Unexpected vertex format, expected Float32x3.");
+            };
+            for y in 0..verts {
+                for x in 0..verts {
+                    vert_pos[y * verts + x][1] = hm.get(x.min(hm.cell_w - 1), y.min(hm.cell_h - 1));
+                }
+            }
+            let cols: Vec<[f32; 4]> = vert_pos
+                .iter()
+                .map(|[_, h, _]| ramp.height_color(*h).to_f32_array())
+                .collect();
+            mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, cols);
+            mesh.compute_normals();
+        };
+
+        sync_chunk_with_heightmap(&mut mesh, &hm, &sm, &ramp, 0, 0);
+        let start = Instant::now();
+        for i in 0..strokes {
+            stroke(&mut hm, i);
+            hm.take_dirty();
+            full_sync(&mut mesh, &hm);
+        }
+        let full = strokes as f64 / start.elapsed().as_secs_f64();
+
+        let start = Instant::now();
+        for i in 0..strokes {
+            stroke(&mut hm, i);
+            let rect = hm.take_dirty().unwrap();
+            sync_chunk_rect(&mut mesh, &hm, &sm, &ramp, 0, 0, rect);
+        }
+        let dirty = strokes as f64 / start.elapsed().as_secs_f64();
+
+        println!("full sync:  {:.0} strokes/sec", full);
+        println!("dirty sync: {:.0} strokes/sec", dirty);
+    }
+
+    #[test]
+    fn png16_round_trip() {
+        let hm = HeightMap::new(100.0, 200.0, 10, 20, 7);
+        let mut bytes = vec![];
+        hm.write_png16(&mut bytes).unwrap();
+
+        let loaded = HeightMap::read_png16(bytes.as_slice(), 1.0, 1.0).unwrap();
+        assert_eq!((loaded.w, loaded.h), (100.0, 200.0));
+        assert_eq!((loaded.cell_w, loaded.cell_h), (10, 20));
+        assert_eq!(loaded.seed, 7);
+        for (a, b) in hm.heights().iter().zip(loaded.heights()) {
+            assert!((a - b).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn raw_f32_round_trip() {
+        let hm = HeightMap::new(100.0, 200.0, 10, 20, 7);
+        let mut bytes = vec![];
+        hm.write_raw_f32(&mut bytes).unwrap();
+
+        let loaded = HeightMap::read_raw_f32(bytes.as_slice()).unwrap();
+        assert_eq!((loaded.w, loaded.h), (100.0, 200.0));
+        assert_eq!(loaded.heights(), hm.heights());
+        assert!(HeightMap::read_raw_f32(&bytes[4..]).is_err());
+
+        // Zero, huge or non-finite sizes are refused, not allocated
+        let mut zero = bytes.clone();
+        zero[4..8].copy_from_slice(&0u32.to_le_bytes());
+        assert!(HeightMap::read_raw_f32(zero.as_slice()).is_err());
+        let mut huge = bytes.clone();
+        huge[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(HeightMap::read_raw_f32(huge.as_slice()).is_err());
+        let mut nan = bytes.clone();
+        nan[12..16].copy_from_slice(&f32::NAN.to_le_bytes());
+        assert!(HeightMap::read_raw_f32(nan.as_slice()).is_err());
     }
 
     #[test]
@@ -5,11 +5,17 @@ use rand::prelude::*;
 
 use crate::constants::{
     SHEET_TOTAL,
+    SHEET_WIDTH,
     CHUNK_SIZE,
+    MAX_BUILDING_SLOPE,
+    ZONE_BUILDING_MARGIN,
 };
 use crate::game::{GameState, OnGameScreen, CollisionLayer};
 use crate::height_map::HeightMap;
-use crate::sheet::TerrainCreated;
+use crate::coords::{SheetPos, WorldPos};
+use crate::sheet::{SheetMode, TerrainCreated};
+use crate::water::WaterMap;
+use crate::zone::{Zone, ZoneRule};
 
 #[derive(Component)]
 struct Peep;
@@ -29,18 +35,26 @@ pub fn spawn_townsfolk(
     _trigger: Trigger<TerrainCreated>,
     mut commands: Commands,
     asset_server: Res<AssetServer>,
-    height_map: Res<HeightMap>
+    mut height_map: ResMut<HeightMap>,
+    water: Res<WaterMap>,
+    mode: Res<SheetMode>,
 ) {
+    // Endless sheets go without, like the powerups. Only the first chunks
+    // exist yet, and anyone left behind would fall through the dropped ones.
+    if *mode == SheetMode::Endless {
+        return;
+    }
+
     // get height_map
-    let mut rng = rand::rng();
-    let w = CHUNK_SIZE;
+    let mut rng = height_map.rng(2);
+    let w = SHEET_WIDTH;
 
     // Add the people
     for _ in 0..200 {
         let x = rng.random_range(0.0..w); // right(0) to left (w)
         let z = rng.random_range(0.0..SHEET_TOTAL - CHUNK_SIZE * 2.0);
-        let y = height_map.pos_to_height(x, z).unwrap_or(0.0);
-        let pos = Vec3::new(x - w / 2.0, y, z - CHUNK_SIZE / 2.0);
+        let y = height_map.sample_height(x, z).unwrap_or(0.0);
+        let pos = SheetPos::new(x, y, z).to_world().0;
 
         commands
             .spawn((
@@ -65,15 +79,28 @@ pub fn spawn_townsfolk(
     );
 
     // Add the things
-    // TODO: a bunch just fall through the ground. fixer it.
     for _ in 0..200 {
-        let (x, z) = height_map.get_random_pos_between_height(0.1, 1.5);
-        let y = height_map.pos_to_height(x, z).unwrap_or(0.0) + 1.0;
-        let pos = Vec3::new(x - w / 2.0, y, z - CHUNK_SIZE);
+        let (x, z) = height_map.get_random_pos_between_height(&mut rng, 0.1, 1.5);
+        // Too steep to build on
+        if height_map.slope_at(x, z).unwrap_or(0.0) > MAX_BUILDING_SLOPE {
+            continue;
+        }
+        // The lakes and rivers were made first, don't build in them
+        let cell = SheetPos::new(x, 0.0, z).to_cell(&height_map);
+        if cell.is_some_and(|c| water.depth(&height_map, c.x, c.y) > 0.0) {
+            continue;
+        }
+        let y = height_map.sample_height(x, z).unwrap_or(0.0) + 1.0;
+        let pos = SheetPos::new(x, y, z).to_world().0;
         let rot = 0.0; // rng.random_range(0.0..PI * 2.0);
+        let up = height_map.sample_normal(x, z).unwrap_or(Vec3::Y);
 
         let thing = things.choose(&mut rng).unwrap();
 
+        // Don't let anyone dig the ground out from under it
+        let radius = thing.1.xz().length() * 0.5 + ZONE_BUILDING_MARGIN;
+        height_map.zones.push(Zone::circle(Vec2::new(x, z), radius, ZoneRule::BuildOnly));
+
         commands
             .spawn((
                 Name::new("House"),
@@ -81,7 +108,9 @@ pub fn spawn_townsfolk(
                 SceneRoot(
                     asset_server
                         .load(GltfAssetLabel::Scene(0).from_asset(thing.0))),
-                Transform::from_translation(pos).with_rotation(Quat::from_rotation_y(rot)),
+                Transform::from_translation(pos).with_rotation(
+                    Quat::from_rotation_arc(Vec3::Y, up) * Quat::from_rotation_y(rot)
+                ),
                 RigidBody::Dynamic,
             ))
             .with_child((
@@ -98,8 +127,8 @@ pub fn spawn_townsfolk(
     for _ in 0..100 {
         let x = rng.random_range(0.0..w); // right(0) to left (w)
         let z = rng.random_range(0.0..SHEET_TOTAL - CHUNK_SIZE * 2.0);
-        let y = height_map.pos_to_height(x, z).unwrap_or(0.0);
-        let pos = Vec3::new(x - w / 2.0, y, z - w / 2.0);
+        let y = height_map.sample_height(x, z).unwrap_or(0.0);
+        let pos = SheetPos::new(x, y, z).to_world().0;
 
         commands
             .spawn((
@@ -149,7 +178,7 @@ fn move_peeps(
             }
         }
 
-        if let Some(h) = height_map.pos_to_height(pos.x + CHUNK_SIZE / 2.0, pos.z + CHUNK_SIZE / 2.0) {
+        if let Some(h) = WorldPos(pos).to_sheet().ground(&height_map) {
             t.translation.y = h;
         } else {
             // out of bounds
@@ -0,0 +1,319 @@
+use std::collections::{HashSet, VecDeque};
+
+use avian3d::prelude::*;
+use bevy::prelude::*;
+use noise::{NoiseFn, Perlin};
+use rand::prelude::*;
+
+use crate::constants::{
+    STONE_RADIUS,
+    WATER_BUOYANCY,
+    WATER_DRAG,
+    WATER_SINK_DEPTH,
+    WATER_SINK_SPEED,
+};
+use crate::coords::WorldPos;
+use crate::game::GamePhase;
+use crate::height_map::HeightMap;
+use crate::stone::{ActiveStone, Stone};
+
+// Heights never go below zero, so this is always dry
+const DRY: f32 = -1.0;
+
+/// Where the water goes on a new sheet
+#[derive(Resource, Clone, Debug)]
+pub struct WaterSettings {
+    pub sea_level: f32, // everything lower is under water
+    pub lakes: usize,
+    pub lake_depth: f32, // deepest a lake fills to above its lowest cell
+    pub lake_max_cells: usize, // a bigger lake would flood the sheet
+    pub rivers: usize,
+    pub river_depth: f32, // channel dug for a river
+    pub river_width: usize, // cells either side of the middle
+}
+
+impl Default for WaterSettings {
+    fn default() -> Self {
+        Self {
+            sea_level: 0.5,
+            lakes: 4,
+            lake_depth: 8.0,
+            lake_max_cells: 3000,
+            rivers: 2,
+            river_depth: 4.0,
+            river_width: 3,
+        }
+    }
+}
+
+/// Height of the water surface over every cell. Same layout as the
+/// HeightMap. There's water wherever it's higher than the ground.
+#[derive(Resource, Clone, Debug)]
+pub struct WaterMap {
+    pub cell_w: usize,
+    pub cell_h: usize,
+    pub sea_level: f32,
+    first_row: usize, // rows before this have been dropped, like the HeightMap
+    levels: Vec<f32>,
+}
+
+/// The stone ended up under water
+#[derive(Debug, Event)]
+pub struct StoneSunk;
+
+/// On a stone that has sunk, so the result can say so
+#[derive(Component)]
+pub struct Sunk;
+
+pub fn water_plugin(app: &mut App) {
+    // Flood the sheet more (or less) with eg. `SEA_LEVEL=3 cargo run`
+    app.insert_resource(WaterSettings {
+        sea_level: std::env::var("SEA_LEVEL")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(WaterSettings::default().sea_level),
+        ..default()
+    });
+    app.add_systems(
+        FixedUpdate,
+        stone_in_water.run_if(in_state(GamePhase::Sculpting))
+    );
+}
+
+impl WaterMap {
+    /// Just the sea
+    pub fn new(cell_w: usize, cell_h: usize, sea_level: f32) -> Self {
+        WaterMap {
+            cell_w,
+            cell_h,
+            sea_level,
+            first_row: 0,
+            levels: vec![sea_level; cell_w * cell_h],
+        }
+    }
+
+    /// Sea, lakes in the dips and rivers dug down the sheet. The
+    /// protected zones are kept dry.
+    pub fn generate(hm: &mut HeightMap, settings: &WaterSettings) -> Self {
+        let mut water = WaterMap::new(hm.cell_w, hm.cell_h, settings.sea_level);
+        water.first_row = hm.first_row();
+        water.levels.drain(..water.first_row * water.cell_w);
+        let mut rng = hm.rng(6);
+
+        for y in hm.first_row()..hm.cell_h {
+            for x in 0..hm.cell_w {
+                if protected(hm, x, y) {
+                    water.set(x, y, DRY);
+                }
+            }
+        }
+
+        let mut tries = settings.lakes * 20;
+        let mut lakes = 0;
+        while lakes < settings.lakes && tries > 0 {
+            tries -= 1;
+            let (x, y) = hm.get_random_cell(&mut rng);
+            if y < hm.cell_h / 10 {
+                // Keep the launch area clear
+                continue;
+            }
+            if water.add_lake(hm, (x, y), settings.lake_depth, settings.lake_max_cells) {
+                lakes += 1;
+            }
+        }
+
+        // Rivers wander down the sheet like the ice lanes
+        let noise = Perlin::new(hm.seed);
+        for river in 0..settings.rivers {
+            let start = rng.random_range(hm.cell_h / 10..hm.cell_h / 2);
+            let len = rng.random_range(hm.cell_h / 4..hm.cell_h / 2);
+            let centre = rng.random_range(0.2..0.8) * hm.cell_w as f32;
+            for y in start..(start + len).min(hm.cell_h) {
+                let wander = noise.get([y as f64 * 0.01, river as f64 * 10.0 + 100.0, 0.0]) as f32;
+                let x = (centre + wander * hm.cell_w as f32 * 0.3) as usize;
+                let w = settings.river_width;
+                for x in x.saturating_sub(w)..=(x + w).min(hm.cell_w - 1) {
+                    let bank = hm.get(x, y);
+                    let dug = -hm.add_height(x, y, -settings.river_depth);
+                    if dug <= 0.0 {
+                        continue;
+                    }
+                    // A little below the banks, so it doesn't spill over them
+                    let level = (bank - dug + settings.river_depth * 0.75).min(bank - 0.3);
+                    water.raise(x, y, level);
+                }
+            }
+        }
+
+        water
+    }
+
+    /// Fill the dip that SHEET cell x/y drains down into, as deep as it
+    /// can go (up to `depth`) without spilling or covering more than
+    /// `max_cells`. Returns true if a lake was made.
+    pub fn add_lake(
+        &mut self,
+        hm: &HeightMap,
+        cell: (usize, usize),
+        depth: f32,
+        max_cells: usize
+    ) -> bool {
+        let low = downhill(hm, cell);
+        let floor = hm.get(low.0, low.1);
+
+        // Find the highest level that stays in the dip
+        let (mut lo, mut hi) = (floor, floor + depth);
+        let mut lake = None;
+        for _ in 0..10 {
+            let level = (lo + hi) * 0.5;
+            match flood(hm, low, level, max_cells) {
+                Some(cells) => {
+                    lake = Some((level, cells));
+                    lo = level;
+                },
+                None => hi = level,
+            }
+        }
+
+        let Some((level, cells)) = lake else { return false; };
+        if level - floor < 1.0 {
+            return false;
+        }
+        for (x, y) in cells {
+            self.raise(x, y, level);
+        }
+        true
+    }
+
+    /// Height of the water surface at SHEET cell x/y. Dropped rows read
+    /// as the first row still held.
+    pub fn level(&self, x: usize, y: usize) -> f32 {
+        let y = y.max(self.first_row) - self.first_row;
+        self.levels[y * self.cell_w + x]
+    }
+
+    /// How deep the water is at SHEET cell x/y
+    pub fn depth(&self, hm: &HeightMap, x: usize, y: usize) -> f32 {
+        (self.level(x, y) - hm.get(x, y)).max(0.0)
+    }
+
+    /// Grow to match a height map that has been extended. It's only sea.
+    pub fn extend(&mut self, hm: &HeightMap) {
+        let rows = hm.cell_h.saturating_sub(self.cell_h);
+        self.levels.resize(self.levels.len() + rows * self.cell_w, self.sea_level);
+        self.cell_h = self.cell_h.max(hm.cell_h);
+    }
+
+    /// Forget the rows before `first_row`, see `HeightMap::drop_rows`
+    pub fn drop_rows(&mut self, first_row: usize) {
+        let first_row = first_row.min(self.cell_h);
+        if first_row <= self.first_row {
+            return;
+        }
+        self.levels.drain(..(first_row - self.first_row) * self.cell_w);
+        self.first_row = first_row;
+    }
+
+    fn set(&mut self, x: usize, y: usize, level: f32) {
+        if x >= self.cell_w || y >= self.cell_h || y < self.first_row {
+            return;
+        }
+        self.levels[(y - self.first_row) * self.cell_w + x] = level;
+    }
+
+    /// Raise the water at SHEET cell x/y, unless it's kept dry
+    fn raise(&mut self, x: usize, y: usize, level: f32) {
+        let cur = self.level(x, y);
+        if cur != DRY && level > cur {
+            self.set(x, y, level);
+        }
+    }
+}
+
+/// Whether SHEET cell x/y is in any protected zone
+fn protected(hm: &HeightMap, x: usize, y: usize) -> bool {
+    !hm.allows(x, y, 1.0) || !hm.allows(x, y, -1.0)
+}
+
+/// Roll downhill from SHEET cell x/y to the bottom of the dip
+fn downhill(hm: &HeightMap, cell: (usize, usize)) -> (usize, usize) {
+    let (mut x, mut y) = cell;
+    loop {
+        let mut next = (x, y);
+        for j in y.saturating_sub(1).max(hm.first_row())..=(y + 1).min(hm.cell_h - 1) {
+            for i in x.saturating_sub(1)..=(x + 1).min(hm.cell_w - 1) {
+                if hm.get(i, j) < hm.get(next.0, next.1) {
+                    next = (i, j);
+                }
+            }
+        }
+        if next == (x, y) {
+            return next;
+        }
+        (x, y) = next;
+    }
+}
+
+/// Cells under `level` joined to `start`, or None if the water would
+/// run off the sheet, into a protected zone or over `max_cells`
+fn flood(
+    hm: &HeightMap,
+    start: (usize, usize),
+    level: f32,
+    max_cells: usize
+) -> Option<Vec<(usize, usize)>> {
+    let mut seen = HashSet::from([start]);
+    let mut cells = vec![];
+    let mut todo = VecDeque::from([start]);
+    while let Some((x, y)) = todo.pop_front() {
+        if x == 0 || x == hm.cell_w - 1 || y <= hm.first_row() || y == hm.cell_h - 1 {
+            return None;
+        }
+        if protected(hm, x, y) || cells.len() >= max_cells {
+            return None;
+        }
+        cells.push((x, y));
+        for (i, j) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+            if hm.get(i, j) < level && seen.insert((i, j)) {
+                todo.push_back((i, j));
+            }
+        }
+    }
+    Some(cells)
+}
+
+/// Water slows stones and holds them up a little. A stone that stops
+/// in deep water has sunk. If it's the one being thrown that ends the throw.
+fn stone_in_water(
+    mut stones: Query<
+        (Entity, &Transform, &mut LinearVelocity, Has<ActiveStone>),
+        (With<Stone>, Without<Sunk>)
+    >,
+    height_map: Res<HeightMap>,
+    water: Res<WaterMap>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    let dt = time.delta_secs();
+    for (e, t, mut vel, active) in stones.iter_mut() {
+        let Some(cell) = WorldPos(t.translation).to_cell(&height_map) else { continue; };
+
+        // How much of the stone is under
+        let over = water.level(cell.x, cell.y) - (t.translation.y - STONE_RADIUS);
+        let under = (over / (STONE_RADIUS * 2.0)).clamp(0.0, 1.0);
+        if under <= 0.0 {
+            continue;
+        }
+
+        vel.0 *= 1.0 - (WATER_DRAG * under * dt).min(1.0);
+        vel.y += WATER_BUOYANCY * under * dt;
+
+        if over > WATER_SINK_DEPTH && vel.length() < WATER_SINK_SPEED {
+            info!("sunk at {:.0}m deep", water.depth(&height_map, cell.x, cell.y));
+            commands.entity(e).insert(Sunk);
+            if active {
+                commands.trigger(StoneSunk);
+            }
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use bevy::{
+    color::palettes::css::{ORANGE, RED, SKY_BLUE},
+    prelude::*,
+};
+
+use crate::constants::{
+    SHEET_WIDTH,
+    TARGET_CENTRE,
+    ZONE_HOLE_RADIUS,
+    ZONE_LAUNCH_LENGTH,
+};
+use crate::height_map::HeightMap;
+use crate::coords::{SheetPos, WorldPos};
+use crate::sheet::SheetMode;
+
+/// Area of the sheet, in SHEET coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ZoneShape {
+    Circle { centre: Vec2, radius: f32 },
+    Rect { min: Vec2, max: Vec2 },
+}
+
+/// What sculpting can do in a zone
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ZoneRule {
+    DigOnly, // no raising
+    BuildOnly, // no lowering
+    Locked, // no editing at all
+}
+
+/// A part of the sheet that is protected from sculpting
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Zone {
+    pub shape: ZoneShape,
+    pub rule: ZoneRule,
+}
+
+impl Zone {
+    pub fn circle(centre: Vec2, radius: f32, rule: ZoneRule) -> Self {
+        Self { shape: ZoneShape::Circle { centre, radius }, rule }
+    }
+
+    pub fn rect(min: Vec2, max: Vec2, rule: ZoneRule) -> Self {
+        Self { shape: ZoneShape::Rect { min, max }, rule }
+    }
+
+    pub fn contains(&self, p: Vec2) -> bool {
+        match self.shape {
+            ZoneShape::Circle { centre, radius } => p.distance_squared(centre) <= radius * radius,
+            ZoneShape::Rect { min, max } => p.cmpge(min).all() && p.cmple(max).all(),
+        }
+    }
+
+    /// Whether a height change of `change` is allowed in the zone
+    pub fn allows(&self, change: f32) -> bool {
+        match self.rule {
+            ZoneRule::DigOnly => change <= 0.0,
+            ZoneRule::BuildOnly => change >= 0.0,
+            ZoneRule::Locked => change == 0.0,
+        }
+    }
+}
+
+/// The zones every sheet gets: no touching where the stone is
+/// launched, and no building up around the hole (if there is one).
+pub fn default_zones(mode: SheetMode) -> Vec<Zone> {
+    let mut zones = vec![
+        Zone::rect(Vec2::ZERO, Vec2::new(SHEET_WIDTH, ZONE_LAUNCH_LENGTH), ZoneRule::Locked),
+    ];
+    if mode == SheetMode::Classic {
+        let hole = WorldPos(TARGET_CENTRE).to_sheet().xz();
+        zones.push(Zone::circle(hole, ZONE_HOLE_RADIUS, ZoneRule::DigOnly));
+    }
+    zones
+}
+
+/// Outline the protected zones on the terrain while sculpting
+pub fn draw_zones(
+    height_map: Res<HeightMap>,
+    mut gizmos: Gizmos,
+) {
+    let to_world = |p: Vec2| {
+        let h = height_map.sample_height(p.x, p.y).unwrap_or(0.0);
+        SheetPos::new(p.x, h + 1.0, p.y).to_world().0
+    };
+    let flat = Quat::from_rotation_x(std::f32::consts::FRAC_PI_2);
+
+    for zone in height_map.zones.iter() {
+        let col = match zone.rule {
+            ZoneRule::DigOnly => ORANGE,
+            ZoneRule::BuildOnly => SKY_BLUE,
+            ZoneRule::Locked => RED,
+        };
+        match zone.shape {
+            ZoneShape::Circle { centre, radius } => {
+                gizmos.circle(Isometry3d::new(to_world(centre), flat), radius, col);
+            },
+            ZoneShape::Rect { min, max } => {
+                let centre = (min + max) * 0.5;
+                gizmos.rect(Isometry3d::new(to_world(centre), flat), max - min, col);
+            },
+        }
+    }
+}